@@ -0,0 +1,146 @@
+/// The result of combining a burst of images with [`crate::CVProcessor::combine_burst`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Burst {
+    /// The average of the images that were kept
+    pub image: Box<[(f64, f64, f64)]>,
+    /// The indices of the images that were averaged
+    pub kept: Vec<usize>,
+    /// The indices of the images that differed too much from the rest of the burst
+    pub rejected: Vec<usize>,
+}
+
+fn channel(pixel: (f64, f64, f64), channel: usize) -> f64 {
+    match channel {
+        0 => pixel.0,
+        1 => pixel.1,
+        _ => pixel.2,
+    }
+}
+
+/// Average the images, leaving out the ones whose pixels at `idxs` differ from the median of the burst by more than `tolerance` on average. The image closest to the median is kept even if all of them differ that much. Bursts of fewer than three images are only averaged, because with two images the median lies halfway between them and there's no telling which one moved.
+pub(crate) fn combine(
+    images: &[impl AsRef<[(f64, f64, f64)]>],
+    idxs: &[usize],
+    tolerance: f64,
+) -> Burst {
+    assert!(!images.is_empty());
+
+    let mut differences = vec![0.; images.len()];
+    let mut values = vec![0.; images.len()];
+    let mut sorted = vec![0.; images.len()];
+    for &idx in idxs {
+        for c in 0..3 {
+            for (value, image) in values.iter_mut().zip(images) {
+                *value = channel(image.as_ref()[idx], c);
+            }
+            sorted.copy_from_slice(&values);
+            sorted.sort_by(f64::total_cmp);
+            let mid = sorted.len() / 2;
+            let median = if sorted.len() % 2 == 0 {
+                (sorted[mid - 1] + sorted[mid]) / 2.
+            } else {
+                sorted[mid]
+            };
+
+            for (difference, value) in differences.iter_mut().zip(&values) {
+                *difference += (value - median).abs();
+            }
+        }
+    }
+    if !idxs.is_empty() {
+        for difference in &mut differences {
+            *difference /= (idxs.len() * 3) as f64;
+        }
+    }
+
+    let (mut kept, mut rejected): (Vec<_>, Vec<_>) = if images.len() < 3 {
+        ((0..images.len()).collect(), Vec::new())
+    } else {
+        (0..images.len()).partition(|&i| differences[i] <= tolerance)
+    };
+    if kept.is_empty() {
+        let closest = (0..images.len())
+            .min_by(|&a, &b| differences[a].total_cmp(&differences[b]))
+            .unwrap();
+        rejected.retain(|&i| i != closest);
+        kept.push(closest);
+    }
+
+    let len = images[0].as_ref().len();
+    let weight = (kept.len() as f64).recip();
+    let mut image = vec![(0., 0., 0.); len].into_boxed_slice();
+    for &i in &kept {
+        let other = images[i].as_ref();
+        assert_eq!(len, other.len());
+        for (pixel, other) in image.iter_mut().zip(other) {
+            pixel.0 += other.0 * weight;
+            pixel.1 += other.1 * weight;
+            pixel.2 += other.2 * weight;
+        }
+    }
+
+    Burst {
+        image,
+        kept,
+        rejected,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::combine;
+
+    #[test]
+    fn test_combine_averages_noise() {
+        let images = [
+            vec![(0.5, 0.5, 0.5), (0.1, 0.2, 0.3)],
+            vec![(0.52, 0.5, 0.48), (0.1, 0.2, 0.3)],
+            vec![(0.48, 0.5, 0.52), (0.1, 0.2, 0.3)],
+        ];
+        let burst = combine(&images, &[0, 1], 0.05);
+        assert_eq!(burst.kept, vec![0, 1, 2]);
+        assert!(burst.rejected.is_empty());
+        for (a, b) in burst.image.iter().zip([(0.5, 0.5, 0.5), (0.1, 0.2, 0.3)]) {
+            assert!((a.0 - b.0).abs() < 1e-9, "{a:?} != {b:?}");
+            assert!((a.1 - b.1).abs() < 1e-9, "{a:?} != {b:?}");
+            assert!((a.2 - b.2).abs() < 1e-9, "{a:?} != {b:?}");
+        }
+    }
+
+    #[test]
+    fn test_combine_rejects_changed_images() {
+        let images = [
+            vec![(0.5, 0.5, 0.5), (0., 0., 0.)],
+            vec![(0.5, 0.5, 0.5), (1., 1., 1.)],
+            vec![(1., 0., 0.), (0., 1., 0.)],
+            vec![(0.5, 0.5, 0.5), (0., 0., 1.)],
+        ];
+        // Only the first pixel counts
+        let burst = combine(&images, &[0], 0.05);
+        assert_eq!(burst.kept, vec![0, 1, 3]);
+        assert_eq!(burst.rejected, vec![2]);
+        assert_eq!(burst.image[0], (0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn test_combine_keeps_closest() {
+        let images = [
+            vec![(0., 0., 0.)],
+            vec![(0.4, 0.4, 0.4)],
+            vec![(1., 1., 1.)],
+        ];
+        let burst = combine(&images, &[0], 0.01);
+        assert_eq!(burst.kept, vec![1]);
+        assert_eq!(burst.rejected, vec![0, 2]);
+        assert_eq!(&*burst.image, &[(0.4, 0.4, 0.4)]);
+    }
+
+    #[test]
+    fn test_combine_averages_pairs() {
+        let images = [vec![(0., 0., 0.)], vec![(1., 1., 1.)]];
+        let burst = combine(&images, &[0], 0.01);
+        assert_eq!(burst.kept, vec![0, 1]);
+        assert!(burst.rejected.is_empty());
+        assert_eq!(&*burst.image, &[(0.5, 0.5, 0.5)]);
+    }
+}
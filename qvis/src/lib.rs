@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use internment::ArcIntern;
 use puzzle_theory::{permutations::Permutation, puzzle_geometry::PuzzleGeometry};
@@ -6,32 +6,55 @@ use serde::{Deserialize, Serialize};
 
 use crate::{inference::Inference, puzzle_matching::Matcher};
 
+mod burst;
 mod inference;
 pub mod puzzle_matching;
+mod resample;
+
+pub use burst::Burst;
+pub use resample::{ResampleError, resample};
 
 /// Processes images for computer vision
 #[derive(Deserialize)]
 #[serde(from = "CVProcessorHelper")]
 pub struct CVProcessor {
     image_size: usize,
+    image_width: Option<usize>,
     puzzle: Arc<PuzzleGeometry>,
     matcher: Matcher,
     inference: Inference,
+    calibrations: usize,
+    /// How many calibrations were given what the processor recognized right before them, which [`CVProcessor::accuracy`] is taken over
+    scored_calibrations: usize,
+    recognized_before_calibration: usize,
 }
 
 #[derive(Serialize, Deserialize)]
 struct CVProcessorHelper {
     image_size: usize,
+    #[serde(default)]
+    image_width: Option<usize>,
     puzzle: Arc<PuzzleGeometry>,
     inference: Inference,
+    #[serde(default)]
+    calibrations: usize,
+    // Processors from before calibrations could go unscored scored every one of them
+    #[serde(default)]
+    scored_calibrations: Option<usize>,
+    #[serde(default)]
+    recognized_before_calibration: usize,
 }
 
 impl Clone for CVProcessor {
     fn clone(&self) -> Self {
         CVProcessor::from(CVProcessorHelper {
             image_size: self.image_size,
+            image_width: self.image_width,
             puzzle: self.puzzle.clone(),
             inference: self.inference.clone(),
+            calibrations: self.calibrations,
+            scored_calibrations: Some(self.scored_calibrations),
+            recognized_before_calibration: self.recognized_before_calibration,
         })
     }
 }
@@ -40,14 +63,18 @@ impl std::fmt::Debug for CVProcessor {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("CVProcessor")
             .field("image_size", &self.image_size)
+            .field("image_width", &self.image_width)
             .field("puzzle", &self.puzzle)
             .field("matcher", &"Matcher { [not shown] }")
             .field("inference", &self.inference)
+            .field("calibrations", &self.calibrations)
+            .field("scored_calibrations", &self.scored_calibrations)
+            .field("recognized_before_calibration", &self.recognized_before_calibration)
             .finish()
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Pixel {
     /// The pixel is not assigned to anything
     Unassigned,
@@ -57,6 +84,187 @@ pub enum Pixel {
     Sticker(usize),
 }
 
+/// Why [`CVProcessor::merge`] couldn't merge the calibration of a sticker
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MergeConflict {
+    /// The sticker is assigned different pixels in the two processors
+    DifferentPixels,
+    /// The face that the sticker is white balanced with is assigned different white balance pixels in the two processors
+    DifferentWhiteBalance,
+}
+
+impl std::fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MergeConflict::DifferentPixels => write!(f, "its pixels differ"),
+            MergeConflict::DifferentWhiteBalance => write!(f, "its face's white balance pixels differ"),
+        }
+    }
+}
+
+/// The stickers whose calibration [`CVProcessor::merge`] merged and the ones that it couldn't
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeReport {
+    pub merged: Vec<usize>,
+    pub unmerged: Vec<(usize, MergeConflict)>,
+}
+
+impl MergeReport {
+    /// Whether every sticker was merged
+    pub fn is_complete(&self) -> bool {
+        self.unmerged.is_empty()
+    }
+}
+
+impl std::fmt::Display for MergeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Merged {} stickers", self.merged.len())?;
+        if self.unmerged.is_empty() {
+            return Ok(());
+        }
+        write!(f, ", could not merge {} stickers:", self.unmerged.len())?;
+        for (sticker, conflict) in &self.unmerged {
+            write!(f, "\n  sticker {sticker}: {conflict}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Why [`CVProcessor::merge`] couldn't merge anything
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeError {
+    /// The processors recognize different puzzles
+    DifferentPuzzle,
+    /// The processors take images of different sizes, so their pixels aren't comparable
+    DifferentImageSize { ours: usize, theirs: usize },
+    /// The processors take images with the same number of pixels, but in different shapes
+    DifferentResolution {
+        ours: (usize, usize),
+        theirs: (usize, usize),
+    },
+}
+
+impl std::fmt::Display for MergeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MergeError::DifferentPuzzle => write!(f, "The processors are for different puzzles"),
+            MergeError::DifferentImageSize { ours, theirs } => write!(
+                f,
+                "The processors take images of different sizes: {ours} and {theirs} pixels"
+            ),
+            MergeError::DifferentResolution { ours, theirs } => write!(
+                f,
+                "The processors take images of different resolutions: {}x{} and {}x{}",
+                ours.0, ours.1, theirs.0, theirs.1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MergeError {}
+
+/// Why [`CVProcessor::set_image_width`] couldn't set the width
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageWidthError {
+    pub width: usize,
+    pub image_size: usize,
+}
+
+impl std::fmt::Display for ImageWidthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "A width of {} doesn't divide the image size of {} pixels",
+            self.width, self.image_size
+        )
+    }
+}
+
+impl std::error::Error for ImageWidthError {}
+
+/// What [`CVProcessor::recognize_stickers`] made of one sticker
+#[derive(Debug, Clone, PartialEq)]
+pub struct StickerRecognition {
+    /// The color that the sticker most likely is when looking at it alone
+    pub likeliest_color: ArcIntern<str>,
+    /// The probability of `likeliest_color` among the colors of the puzzle, between zero and one
+    pub confidence: f64,
+    /// The color of the sticker in the state that the whole puzzle was matched to
+    pub matched_color: ArcIntern<str>,
+    /// The probability of every color of the puzzle, as returned by [`CVProcessor::sticker_color_probabilities`]
+    pub probabilities: HashMap<ArcIntern<str>, f64>,
+    /// Whether something covered the sticker, like a robot gripper or a hand. The state was matched without looking at occluded stickers, so their probabilities mean nothing.
+    pub occluded: bool,
+}
+
+impl StickerRecognition {
+    /// Whether the matched state disagrees with what the sticker looks like on its own. Occluded stickers never disagree.
+    pub fn disagrees(&self) -> bool {
+        !self.occluded && self.likeliest_color != self.matched_color
+    }
+}
+
+/// Scale the confidences of a sticker so that they add up to one
+fn normalize(confidences: &HashMap<ArcIntern<str>, f64>) -> HashMap<ArcIntern<str>, f64> {
+    let total = confidences.values().sum::<f64>();
+    confidences
+        .iter()
+        .map(|(color, &confidence)| {
+            let probability = if total > 0. { confidence / total } else { 0. };
+            (ArcIntern::clone(color), probability)
+        })
+        .collect()
+}
+
+/// A record of the images that a `CVProcessor` was calibrated with, which lets calibration be re-derived after pixels are reassigned with [`CVProcessor::reassign`]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CalibrationDataset {
+    samples: Vec<(Box<[(f64, f64, f64)]>, Permutation)>,
+    #[serde(default)]
+    max_len: Option<usize>,
+}
+
+impl CalibrationDataset {
+    pub fn new() -> CalibrationDataset {
+        CalibrationDataset::default()
+    }
+
+    /// Create a dataset that only keeps the `max_len` most recent images, since every image takes as much memory as a picture. Reassigned stickers are then recalibrated with only those images.
+    pub fn with_max_len(max_len: usize) -> CalibrationDataset {
+        CalibrationDataset {
+            samples: Vec::new(),
+            max_len: Some(max_len),
+        }
+    }
+
+    /// Record an image of the puzzle in the given state, as passed to [`CVProcessor::calibrate`]. Forgets the oldest image if the dataset is full.
+    pub fn push(&mut self, image: Box<[(f64, f64, f64)]>, state: Permutation) {
+        if let Some(max_len) = self.max_len {
+            if max_len == 0 {
+                return;
+            }
+            if self.samples.len() >= max_len {
+                self.samples.drain(..=self.samples.len() - max_len);
+            }
+        }
+        self.samples.push((image, state));
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&[(f64, f64, f64)], &Permutation)> {
+        self.samples
+            .iter()
+            .map(|(image, state)| (&**image, state))
+    }
+}
+
 impl CVProcessor {
     /// Create a new `CVProcessor` that recognizes the given puzzle in images. `image_size` specifies the number of pixels in the image. The CV algorithm does not care about rows and columns.
     ///
@@ -76,21 +284,102 @@ impl CVProcessor {
     ) -> CVProcessor {
         CVProcessor {
             image_size,
+            image_width: None,
             inference: Inference::new(assignment, &puzzle),
             matcher: Matcher::new(&puzzle),
             puzzle,
+            calibrations: 0,
+            scored_calibrations: 0,
+            recognized_before_calibration: 0,
         }
     }
 
+    /// Get the number of pixels in the images that this processor takes
+    pub fn image_size(&self) -> usize {
+        self.image_size
+    }
+
+    /// Get the width of the images that this processor takes, if it was set with [`CVProcessor::set_image_width`]. Processors don't know their width otherwise, since only the number of pixels matters to them.
+    pub fn image_width(&self) -> Option<usize> {
+        self.image_width
+    }
+
+    /// Get the width and height of the images that this processor takes, if its width is known
+    pub fn resolution(&self) -> Option<(usize, usize)> {
+        self.image_width.map(|width| (width, self.image_size / width))
+    }
+
+    /// Record the width of the images that this processor takes, so that images of other resolutions can be resampled to fit it with [`CVProcessor::fit_image`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error without changing anything if the width doesn't divide the image size.
+    pub fn set_image_width(&mut self, width: usize) -> Result<(), ImageWidthError> {
+        if width == 0 || self.image_size % width != 0 {
+            return Err(ImageWidthError {
+                width,
+                image_size: self.image_size,
+            });
+        }
+
+        self.image_width = Some(width);
+        Ok(())
+    }
+
+    /// Resample an image with the given width and height to the resolution of this processor using [`resample`]. Returns `None` if the image can't be resampled, or if the resolution of this processor isn't known and the image doesn't have the right number of pixels already.
+    pub fn fit_image(
+        &self,
+        image: &[(f64, f64, f64)],
+        width: usize,
+        height: usize,
+    ) -> Option<Box<[(f64, f64, f64)]>> {
+        match self.resolution() {
+            Some(resolution) => resample(image, (width, height), resolution).ok(),
+            None if image.len() == self.image_size => Some(image.into()),
+            None => None,
+        }
+    }
+
+    /// Get the number of images that this processor was calibrated with using [`CVProcessor::calibrate`]
+    pub fn calibrations(&self) -> usize {
+        self.calibrations
+    }
+
+    /// Get the fraction of calibration images that this processor recognized correctly right before being calibrated with them, which estimates how well it recognizes images that it hasn't seen. Only calibrations that were given what the processor recognized count, see [`CVProcessor::calibrate`]. Returns `None` if no calibration counted.
+    ///
+    /// Every calibration counts equally, so the first few calibrations of a new processor drag this down for a while.
+    pub fn accuracy(&self) -> Option<f64> {
+        if self.scored_calibrations == 0 {
+            return None;
+        }
+        Some(self.recognized_before_calibration as f64 / self.scored_calibrations as f64)
+    }
+
     /// Calibrate the CV processor with an image of the puzzle in the given state.
-    pub fn calibrate(&mut self, image: &[(f64, f64, f64)], state: &Permutation) {
+    ///
+    /// `recognized` is the state that the processor recognized in the image right before, if the caller recognized it anyway, which counts toward [`CVProcessor::accuracy`]. Calibrating doesn't recognize the image itself because that costs as much as calibrating.
+    pub fn calibrate(
+        &mut self,
+        image: &[(f64, f64, f64)],
+        state: &Permutation,
+        recognized: Option<&Permutation>,
+    ) {
         assert_eq!(self.image_size, image.len());
 
+        if let Some(recognized) = recognized {
+            self.scored_calibrations += 1;
+            if recognized == state {
+                self.recognized_before_calibration += 1;
+            }
+        }
         self.inference
             .calibrate(image, state, &self.puzzle.permutation_group());
+        self.calibrations += 1;
     }
 
     /// Process an image and return the most likely state that the puzzle appears to be in, along with the confidence in the prediction. This is guaranteed to be a valid member of the group.
+    ///
+    /// Stickers that something covers are treated as if nothing was known about them, see [`CVProcessor::recognize_stickers`] to find out which ones were.
     pub fn process_image(&self, image: &[(f64, f64, f64)]) -> (Permutation, f64) {
         self.matcher.most_likely(
             &self
@@ -100,6 +389,151 @@ impl CVProcessor {
         )
     }
 
+    /// Process an image and return the probability of every color of the puzzle for every sticker, judging each sticker on its own, indexed by sticker. The probabilities of a sticker add up to one unless nothing is known about it.
+    ///
+    /// Unlike [`CVProcessor::process_image`], this doesn't consider which states are possible, so it shows what every sticker looks like before the matcher picks the most likely state.
+    pub fn sticker_color_probabilities(
+        &self,
+        image: &[(f64, f64, f64)],
+    ) -> Box<[HashMap<ArcIntern<str>, f64>]> {
+        self.inference
+            .infer(image, &self.puzzle.permutation_group())
+            .iter()
+            .map(normalize)
+            .collect()
+    }
+
+    /// Like [`CVProcessor::process_image`], but also return what every sticker looks like on its own, which color it has in the matched state and whether it was occluded, indexed by sticker
+    pub fn recognize_stickers(
+        &self,
+        image: &[(f64, f64, f64)],
+    ) -> (Permutation, f64, Box<[StickerRecognition]>) {
+        let group = self.puzzle.permutation_group();
+        let (confidences, occluded) = self.inference.infer_with_occlusion(image, &group);
+        let (state, confidence) = self.matcher.most_likely(&confidences, &self.puzzle);
+
+        let stickers = confidences
+            .iter()
+            .enumerate()
+            .map(|(sticker, colors)| {
+                let probabilities = normalize(colors);
+                let (likeliest_color, &confidence) = probabilities
+                    .iter()
+                    .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                    .unwrap();
+                StickerRecognition {
+                    likeliest_color: ArcIntern::clone(likeliest_color),
+                    confidence,
+                    matched_color: ArcIntern::clone(&group.facelet_colors()[state.state().get(sticker)]),
+                    probabilities,
+                    occluded: occluded.contains(&sticker),
+                }
+            })
+            .collect();
+
+        (state, confidence, stickers)
+    }
+
+    /// Combine a burst of images of the same scene, taken in quick succession, into one image with less sensor noise and flicker. Images whose pixels that are assigned to stickers or white balance differ from the median of the burst by more than `tolerance` on average are left out, because something moved in them or they are blurred by motion, and the rest are averaged. Bursts of fewer than three images are always averaged whole.
+    pub fn combine_burst(
+        &self,
+        images: &[impl AsRef<[(f64, f64, f64)]>],
+        tolerance: f64,
+    ) -> Burst {
+        for image in images {
+            assert_eq!(self.image_size, image.as_ref().len());
+        }
+
+        let idxs = self
+            .pixel_assignment_locations()
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, &assigned)| assigned.then_some(idx))
+            .collect::<Vec<_>>();
+        burst::combine(images, &idxs, tolerance)
+    }
+
+    /// Replace the pixels of the given stickers and white balance faces with the pixels assigned to them in `assignment`, which is in the same format as the assignment passed to [`CVProcessor::new`]. Everything not in `targets` is left alone, including its calibration.
+    ///
+    /// Without a dataset, pixels that stay assigned to the same sticker keep their calibration and new pixels start out uncalibrated. With a dataset, the reassigned stickers are recalibrated from scratch using every image in it, as are the stickers of any face whose white balance was reassigned.
+    pub fn reassign(
+        &mut self,
+        assignment: &[Pixel],
+        targets: &[Pixel],
+        dataset: Option<&CalibrationDataset>,
+    ) {
+        assert_eq!(self.image_size, assignment.len());
+
+        let group = self.puzzle.permutation_group();
+        let stale = self.inference.reassign(assignment, targets, &group);
+
+        if let Some(dataset) = dataset {
+            for (image, _) in dataset.iter() {
+                assert_eq!(self.image_size, image.len());
+            }
+            self.inference.recalibrate(&stale, dataset.iter(), &group);
+        }
+    }
+
+    /// Add the calibration of `other`, which was calibrated separately for the same puzzle and camera, to this processor. This is useful for combining the work of people calibrating the same rig in parallel.
+    ///
+    /// Only stickers that are assigned the same pixels in both processors and whose faces are assigned the same white balance pixels are merged, and the returned report says which stickers could and couldn't be. Calibration counts are added together regardless, so [`CVProcessor::accuracy`] reflects both processors.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error without changing anything if the processors are for different puzzles or image sizes.
+    pub fn merge(&mut self, other: &CVProcessor) -> Result<MergeReport, MergeError> {
+        if self.image_size != other.image_size {
+            return Err(MergeError::DifferentImageSize {
+                ours: self.image_size,
+                theirs: other.image_size,
+            });
+        }
+        if let (Some(ours), Some(theirs)) = (self.resolution(), other.resolution())
+            && ours != theirs
+        {
+            return Err(MergeError::DifferentResolution { ours, theirs });
+        }
+        let group = self.puzzle.permutation_group();
+        let other_group = other.puzzle.permutation_group();
+        // Puzzles with the same colors can still have different stickers or moves
+        if group.facelet_count() != other_group.facelet_count()
+            || group.facelet_colors() != other_group.facelet_colors()
+            || group.generators().count() != other_group.generators().count()
+            || !group.generators().all(|(_, generator)| {
+                other_group
+                    .generators()
+                    .any(|(_, other_generator)| other_generator == generator)
+            })
+        {
+            return Err(MergeError::DifferentPuzzle);
+        }
+
+        let (merged, unmerged) = self.inference.merge(&other.inference, &group);
+        self.calibrations += other.calibrations;
+        self.scored_calibrations += other.scored_calibrations;
+        self.recognized_before_calibration += other.recognized_before_calibration;
+        self.image_width = self.image_width.or(other.image_width);
+
+        Ok(MergeReport { merged, unmerged })
+    }
+
+    /// Weight every pixel assigned to a sticker by its entry in `weights`, which is the same size as the image. Pixels with larger weights count for more when deciding what color their sticker is, which is useful for trusting the middle of a sticker more than its edges. Every pixel starts out with a weight of one, and weights that aren't positive and finite are treated as one.
+    pub fn set_pixel_weights(&mut self, weights: &[f64]) {
+        assert_eq!(self.image_size, weights.len());
+
+        self.inference.set_weights(weights);
+    }
+
+    /// Get the weight of every pixel in the image, as set by [`CVProcessor::set_pixel_weights`]. Pixels that aren't assigned to a sticker have a weight of one.
+    pub fn pixel_weights(&self) -> Box<[f64]> {
+        let mut ret = vec![1.; self.image_size].into_boxed_slice();
+        for pixel in self.inference.pixels_by_sticker.iter().flatten() {
+            ret[pixel.idx] = pixel.weight;
+        }
+        ret
+    }
+
     /// Get the locations of pixels that are assigned to something, either a sticker or white balance. This is useful for debugging and visualization.
     pub fn pixel_assignment_locations(&self) -> Box<[bool]> {
         let mut ret = vec![false; self.image_size].into_boxed_slice();
@@ -111,6 +545,22 @@ impl CVProcessor {
         }
         ret
     }
+
+    /// Get the pixel assignment that this `CVProcessor` was created with, in the same format as the assignment passed to [`CVProcessor::new`].
+    pub fn pixel_assignment(&self) -> Box<[Pixel]> {
+        let mut ret = vec![Pixel::Unassigned; self.image_size].into_boxed_slice();
+        for (sticker, pixels) in self.inference.pixels_by_sticker.iter().enumerate() {
+            for pixel in pixels {
+                ret[pixel.idx] = Pixel::Sticker(sticker);
+            }
+        }
+        for (face, idxs) in &self.inference.white_balance_by_face {
+            for &idx in idxs {
+                ret[idx] = Pixel::WhiteBalance(ArcIntern::clone(face));
+            }
+        }
+        ret
+    }
 }
 
 impl Serialize for CVProcessor {
@@ -120,26 +570,39 @@ impl Serialize for CVProcessor {
     {
         let CVProcessor {
             image_size,
+            image_width,
             puzzle,
             matcher: _,
             inference,
+            calibrations,
+            scored_calibrations,
+            recognized_before_calibration,
         } = self;
         // (&image_size, &puzzle, &inference).serialize(serializer)
         CVProcessorHelper {
             image_size: *image_size,
+            image_width: *image_width,
             puzzle: puzzle.clone(),
             inference: inference.clone(),
+            calibrations: *calibrations,
+            scored_calibrations: Some(*scored_calibrations),
+            recognized_before_calibration: *recognized_before_calibration,
         }.serialize(serializer)
     }
 }
 
 impl From<CVProcessorHelper> for CVProcessor {
-    fn from(CVProcessorHelper { image_size, puzzle, inference }: CVProcessorHelper) -> Self {
+    fn from(CVProcessorHelper { image_size, image_width, puzzle, inference, calibrations, scored_calibrations, recognized_before_calibration }: CVProcessorHelper) -> Self {
         CVProcessor {
             image_size,
+            // Saved processors may come from anywhere, so forget widths that couldn't have been set
+            image_width: image_width.filter(|&width| width > 0 && image_size % width == 0),
             matcher: Matcher::new(&puzzle),
             puzzle,
             inference,
+            calibrations,
+            scored_calibrations: scored_calibrations.unwrap_or(calibrations),
+            recognized_before_calibration,
         }
     }
 }
@@ -0,0 +1,182 @@
+/// The source pixels that every destination pixel covers along one axis, as the first of them and the fraction of the destination pixel that each of them covers
+fn area_weights(from: usize, to: usize) -> Vec<(usize, Vec<f64>)> {
+    let scale = from as f64 / to as f64;
+    (0..to)
+        .map(|i| {
+            let start = i as f64 * scale;
+            let end = (i + 1) as f64 * scale;
+            let first = start.floor() as usize;
+            let last = (end.ceil() as usize).min(from);
+            let weights = (first..last)
+                .map(|j| (end.min(j as f64 + 1.) - start.max(j as f64)).max(0.) / scale)
+                .collect();
+            (first, weights)
+        })
+        .collect()
+}
+
+fn weighted_sum<'a>(
+    pixels: impl Iterator<Item = &'a (f64, f64, f64)>,
+    weights: &[f64],
+) -> (f64, f64, f64) {
+    pixels
+        .zip(weights)
+        .fold((0., 0., 0.), |(r, g, b), (pixel, weight)| {
+            (
+                r + pixel.0 * weight,
+                g + pixel.1 * weight,
+                b + pixel.2 * weight,
+            )
+        })
+}
+
+/// Why [`resample`] couldn't resample an image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResampleError {
+    /// The image doesn't have as many pixels as its width and height say
+    WrongSize { expected: usize, actual: usize },
+    /// A width or height is zero
+    Empty,
+}
+
+impl std::fmt::Display for ResampleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResampleError::WrongSize { expected, actual } => write!(
+                f,
+                "The image has {actual} pixels but its width and height say {expected}"
+            ),
+            ResampleError::Empty => write!(f, "Images can't be resampled from or to nothing"),
+        }
+    }
+}
+
+impl std::error::Error for ResampleError {}
+
+/// Resample an image stored row by row from `from` to `to`, both given as width and height. Every pixel becomes the average of the area of the image that it covers, so shrinking a picture from a high resolution camera averages out its noise instead of skipping pixels.
+///
+/// Pictures should be resampled with this before being given to a [`crate::CVProcessor`], so that they are resampled the same way wherever they come from.
+///
+/// # Errors
+///
+/// Returns an error if any width or height is zero or `image` doesn't have `from.0 * from.1` pixels.
+pub fn resample(
+    image: &[(f64, f64, f64)],
+    from: (usize, usize),
+    to: (usize, usize),
+) -> Result<Box<[(f64, f64, f64)]>, ResampleError> {
+    if [from.0, from.1, to.0, to.1].contains(&0) {
+        return Err(ResampleError::Empty);
+    }
+    if image.len() != from.0 * from.1 {
+        return Err(ResampleError::WrongSize {
+            expected: from.0 * from.1,
+            actual: image.len(),
+        });
+    }
+    if from == to {
+        return Ok(image.into());
+    }
+
+    // Horizontally first, so that every row of the image is read only once
+    let columns = area_weights(from.0, to.0);
+    let mut narrowed = Vec::with_capacity(to.0 * from.1);
+    for row in image.chunks_exact(from.0) {
+        for (first, weights) in &columns {
+            narrowed.push(weighted_sum(row[*first..].iter(), weights));
+        }
+    }
+
+    let rows = area_weights(from.1, to.1);
+    let mut out = Vec::with_capacity(to.0 * to.1);
+    for (first, weights) in &rows {
+        for x in 0..to.0 {
+            out.push(weighted_sum(
+                narrowed[first * to.0 + x..].iter().step_by(to.0),
+                weights,
+            ));
+        }
+    }
+    Ok(out.into_boxed_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ResampleError, resample};
+
+    fn assert_close(a: &[(f64, f64, f64)], b: &[(f64, f64, f64)]) {
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b) {
+            assert!((a.0 - b.0).abs() < 1e-9, "{a:?} != {b:?}");
+            assert!((a.1 - b.1).abs() < 1e-9, "{a:?} != {b:?}");
+            assert!((a.2 - b.2).abs() < 1e-9, "{a:?} != {b:?}");
+        }
+    }
+
+    #[test]
+    fn test_resample_same_size() {
+        let image = (0..12).map(|i| (i as f64, 0., 1.)).collect::<Vec<_>>();
+        assert_close(&resample(&image, (4, 3), (4, 3)).unwrap(), &image);
+    }
+
+    #[test]
+    fn test_resample_averages_blocks() {
+        #[rustfmt::skip]
+        let image = [
+            (0., 0., 0.), (2., 0., 0.), (0., 4., 0.), (0., 8., 0.),
+            (4., 0., 0.), (6., 0., 0.), (0., 4., 8.), (0., 0., 8.),
+        ];
+        assert_close(
+            &resample(&image, (4, 2), (2, 1)).unwrap(),
+            &[(3., 0., 0.), (0., 4., 4.)],
+        );
+    }
+
+    #[test]
+    fn test_resample_uneven() {
+        let image = vec![(0.25, 0.5, 0.75); 7 * 5];
+        assert_close(
+            &resample(&image, (7, 5), (3, 2)).unwrap(),
+            &[(0.25, 0.5, 0.75); 6],
+        );
+
+        // Every destination pixel covers one and a half source pixels
+        let image = [(0., 0., 0.), (1., 0., 0.), (2., 0., 0.)];
+        assert_close(
+            &resample(&image, (3, 1), (2, 1)).unwrap(),
+            &[(1. / 3., 0., 0.), (5. / 3., 0., 0.)],
+        );
+    }
+
+    #[test]
+    fn test_resample_enlarges() {
+        let image = [(0., 0., 0.), (1., 1., 1.)];
+        assert_close(
+            &resample(&image, (2, 1), (4, 2)).unwrap(),
+            &[
+                (0., 0., 0.),
+                (0., 0., 0.),
+                (1., 1., 1.),
+                (1., 1., 1.),
+                (0., 0., 0.),
+                (0., 0., 0.),
+                (1., 1., 1.),
+                (1., 1., 1.),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_resample_rejects_bad_sizes() {
+        let image = [(0., 0., 0.); 6];
+        assert_eq!(resample(&image, (3, 2), (0, 2)), Err(ResampleError::Empty));
+        assert_eq!(resample(&image, (0, 2), (3, 2)), Err(ResampleError::Empty));
+        assert_eq!(
+            resample(&image, (2, 2), (1, 1)),
+            Err(ResampleError::WrongSize {
+                expected: 4,
+                actual: 6
+            })
+        );
+    }
+}
@@ -2,8 +2,14 @@
 
 use crate::{
     messages_logger::MessagesLogger,
-    video::{OnceBarrier, Video, pixel_assignment_command, take_picture_command},
+    pixel_assignment::changed_targets,
+    pixel_assignment_editor::{AssignmentImage, PixelAssignmentEditor},
+    video::{
+        CameraSettings, DEFAULT_STABLE_MS, DEFAULT_WIDTH, OnceBarrier, Video,
+        assignment_image_command, combine_burst, take_burst_command, wait_until_stable_command,
+    },
 };
+use internment::ArcIntern;
 use leptos::{html, prelude::*, task::spawn_local};
 use leptos_use::{
     ConstraintExactIdeal, FacingMode, UseUserMediaOptions, UseUserMediaReturn,
@@ -12,21 +18,188 @@ use leptos_use::{
 use leptos_ws::ChannelSignal;
 use log::{LevelFilter, info, warn};
 use puzzle_theory::{permutations::Permutation, puzzle_geometry::parsing::puzzle};
-use qvis::{CVProcessor, Pixel};
+use qvis::{CVProcessor, CalibrationDataset, Pixel, StickerRecognition};
 use serde::{Deserialize, Serialize};
 use server_fn::codec::{MultipartData, MultipartFormData};
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
 
 pub const TAKE_PICTURE_CHANNEL: &str = "take_picture_channel";
+/// The puzzle that processors are made for
+pub const PUZZLE_NAME: &str = "3x3";
+pub const CV_PROCESSOR_CHANNEL: &str = "cv_processor_channel";
+/// How often the browser tells the server that it is still there to take pictures
+pub const CLIENT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
+/// How many calibration pictures the browser keeps for recalibrating reassigned stickers. Each one takes about 13 MB at the default capture width.
+const MAX_CALIBRATION_IMAGES: usize = 20;
+
+/// Tells browser tabs apart, so that the server can ask one of them to take a picture and notice when that one is gone
+pub type ClientId = u64;
+/// Tells robot commands apart, so that a late or duplicate answer isn't taken for the answer to another command
+pub type RequestId = u64;
+
+/// A request for a picture, which only the client that it is for answers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PictureRequest {
+    pub client: ClientId,
+    /// The same for every client that the request is sent to, in case the first one seems gone but answers anyway
+    pub id: RequestId,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TakePictureMessage {
     // Request
-    TakePicture,
-    Calibrate(Permutation),
-    // Response
-    PermutationResult(Permutation, f64),
-    Calibrated,
+    TakePicture(PictureRequest),
+    Calibrate(PictureRequest, Permutation),
+    // Response to the request with the given id, with how long the browser waited for the puzzle to hold still and which stickers were occluded
+    PermutationResult(RequestId, Permutation, f64, Duration, Vec<usize>),
+    Calibrated(RequestId, Duration),
+    /// The picture couldn't be taken or doesn't fit the processor
+    Failed(RequestId, String),
+}
+
+/// The id of this browser tab, which it keeps until it is closed or reloaded
+fn client_id() -> ClientId {
+    static CLIENT_ID: OnceLock<ClientId> = OnceLock::new();
+    // Random so that tabs don't need to agree on ids, and below 2^53 so that JavaScript can hold them
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    *CLIENT_ID.get_or_init(|| (web_sys::js_sys::Math::random() * 2f64.powi(53)) as ClientId)
+}
+
+/// What the browser replies when the pictures it took can't be resampled to fit the processor
+fn picture_mismatch(request: RequestId) -> TakePictureMessage {
+    TakePictureMessage::Failed(
+        request,
+        "The picture doesn't fit the CVProcessor, which doesn't know its resolution".to_owned(),
+    )
+}
+
+/// The server's processor, which it pushes to clients whenever it changes and when they connect
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CVProcessorMessage(pub CVProcessor);
+
+/// A checkpoint of the server's processor, which the server saves periodically while calibrating
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckpointInfo {
+    pub name: String,
+    /// How many pictures the processor was calibrated with
+    pub calibrations: usize,
+    /// How long ago the checkpoint was saved, as of listing it
+    pub age_secs: u64,
+}
+
+/// The kinds of things that the server saves by name in its model directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ModelKind {
+    CVProcessor,
+    PixelAssignment,
+}
+
+/// Where the browser can download the processor saved under the given name
+pub fn cv_processor_download_url(name: &str) -> String {
+    format!("/models/cv_processors/{name}")
+}
+
+/// A saved processor for one rig and lighting setup, see [`crate::registry`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProfileInfo {
+    pub name: String,
+    pub puzzle: String,
+    /// The width and height of the pictures that the processor takes
+    pub resolution: (usize, usize),
+    /// When the profile was created, in seconds since the Unix epoch
+    pub created: u64,
+    pub calibrations: usize,
+    /// See [`CVProcessor::accuracy`]
+    pub accuracy: Option<f64>,
+    pub tags: Vec<String>,
+    /// How the camera was set up for the processor
+    #[serde(default)]
+    pub camera: CameraSettings,
+}
+
+/// Formats seconds since the Unix epoch as a UTC date and time, like `2024-03-01 14:05`
+fn format_date(unix_secs: u64) -> String {
+    let days = unix_secs / 86400;
+    let secs_of_day = unix_secs % 86400;
+    // Howard Hinnant's `civil_from_days`, for days since 1970-01-01, which are never negative here
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}",
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60
+    )
+}
+
+/// Asks for a name or other text with `window.prompt`, returning `None` if the user cancels or enters nothing
+fn prompt_for(message: &str, default: &str) -> Option<String> {
+    match web_sys::window()
+        .unwrap()
+        .prompt_with_message_and_default(message, default)
+    {
+        Ok(Some(text)) if !text.trim().is_empty() => Some(text.trim().to_owned()),
+        Ok(Some(_)) => {
+            warn!("Cancelled: nothing was entered");
+            None
+        }
+        Ok(None) => {
+            warn!("Cancelled: user cancelled dialog");
+            None
+        }
+        Err(err) => {
+            warn!("Cancelled: prompt failed: {err:?}");
+            None
+        }
+    }
+}
+
+fn new_calibration_dataset() -> CalibrationDataset {
+    CalibrationDataset::with_max_len(MAX_CALIBRATION_IMAGES)
+}
+
+/// The stickers that something covered in a picture, see [`StickerRecognition::occluded`]
+pub(crate) fn occluded_stickers(stickers: &[StickerRecognition]) -> Vec<usize> {
+    stickers
+        .iter()
+        .enumerate()
+        .filter_map(|(sticker, recognition)| recognition.occluded.then_some(sticker))
+        .collect()
+}
+
+/// Lists the colors of a sticker from most to least likely, with their probabilities in percent
+fn format_probabilities(probabilities: &HashMap<ArcIntern<str>, f64>) -> String {
+    let mut probabilities = probabilities.iter().collect::<Vec<_>>();
+    probabilities.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    probabilities
+        .into_iter()
+        .map(|(color, probability)| format!("{color} {:.1}%", probability * 100.))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn format_age(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{secs} s"),
+        60..3600 => format!("{} min", secs / 60),
+        3600..86400 => format!("{} h", secs / 3600),
+        _ => format!("{} days", secs / 86400),
+    }
 }
 
 pub fn shell(options: LeptosOptions) -> impl IntoView {
@@ -64,7 +237,7 @@ pub fn App() -> impl IntoView {
         context.set_on_connect(move || {
             info!("Established connection with server");
             spawn_local(async move {
-                print_ready().await.unwrap();
+                print_ready(client_id()).await.unwrap();
             });
         });
         context.set_on_disconnect(move || {
@@ -73,9 +246,17 @@ pub fn App() -> impl IntoView {
         context.set_on_reconnect(move || {
             info!("Re-established connection with server");
             spawn_local(async move {
-                print_ready().await.unwrap();
+                print_ready(client_id()).await.unwrap();
             });
         });
+        spawn_local(async move {
+            let interval = u32::try_from(CLIENT_HEARTBEAT_INTERVAL.as_millis()).unwrap();
+            loop {
+                gloo_timers::future::TimeoutFuture::new(interval).await;
+                // The server notices missed heartbeats by itself, so there's nothing to do if one fails
+                let _ = client_heartbeat(client_id()).await;
+            }
+        });
     }
 
     let use_user_media_return = use_user_media_with_options(UseUserMediaOptions::default().video(
@@ -91,13 +272,73 @@ pub fn App() -> impl IntoView {
     let cv_overlay_ref: NodeRef<html::Canvas> = NodeRef::new();
     let (overflowing, set_overflowing) = signal(true);
     let playing_barrier = OnceBarrier::new();
-    let cube3 = puzzle("3x3");
+    let cube3 = puzzle(PUZZLE_NAME);
     let (cv_available_tx, cv_available_rx) = tokio::sync::watch::channel(None::<CVProcessor>);
+    let calibration_dataset = StoredValue::new(new_calibration_dataset());
+    let camera_settings = RwSignal::new(CameraSettings::default());
+    let capture_width = RwSignal::new(DEFAULT_WIDTH);
+    // The profile whose camera settings were applied last, so that they aren't applied again over changes made since
+    let camera_settings_profile = StoredValue::new(None::<String>);
 
-    let take_picture_channel = ChannelSignal::new(TAKE_PICTURE_CHANNEL).unwrap();
+    // The server has the authoritative processor and pushes it whenever it changes and when we connect
+    {
+        let cv_available_tx = cv_available_tx.clone();
+        ChannelSignal::new(CV_PROCESSOR_CHANNEL)
+            .unwrap()
+            .on_client(move |CVProcessorMessage(cv_processor): &CVProcessorMessage| {
+                info!("Received CVProcessor from the server");
+                // Take pictures at the resolution that the processor was made for
+                if let Some(width) = cv_processor.image_width()
+                    && let Ok(width) = u32::try_from(width)
+                    && width != capture_width.get_untracked()
+                {
+                    capture_width.set(width);
+                }
+                cv_available_tx.send_replace(Some(cv_processor.clone()));
+                // The processor may come from another profile, which needs its own camera settings
+                spawn_local(async move {
+                    match active_camera_settings().await {
+                        Ok(Some((profile, settings))) => {
+                            if camera_settings_profile.get_value().as_ref() != Some(&profile) {
+                                info!("Applying the camera settings of profile {profile}");
+                                camera_settings_profile.set_value(Some(profile));
+                                camera_settings.set(settings);
+                            }
+                        }
+                        Ok(None) => {}
+                        Err(err) => warn!("Failed to get the camera settings: {err}"),
+                    }
+                });
+            })
+            .unwrap();
+    }
+    // Call this after changing the processor here, so that the server and every other client get the change
+    let share_cv_processor_with_server = {
+        let cv_available_rx = cv_available_rx.clone();
+        move || {
+            let Some(cv_processor) = cv_available_rx.borrow().clone() else {
+                return;
+            };
+            let cv_processor = leptos::serde_json::to_string(&cv_processor).unwrap();
+            spawn_local(async move {
+                if let Err(err) = share_cv_processor(cv_processor).await {
+                    warn!("Failed to share CVProcessor with the server: {err}");
+                }
+            });
+        }
+    };
 
-    let pixel_assignment_action =
-        Action::new_local(|data: &web_sys::FormData| pixel_assignment(data.clone().into()));
+    let take_picture_channel = ChannelSignal::new(TAKE_PICTURE_CHANNEL).unwrap();
+    let (assignment_image, set_assignment_image) =
+        signal(None::<(AssignmentImage, Option<Box<[Pixel]>>)>);
+    let (live_recognition, set_live_recognition) = signal(false);
+    // How many frames every picture is averaged from
+    let (burst_frames, set_burst_frames) = signal(1usize);
+    // How long the puzzle has to hold still before robot pictures are taken, in milliseconds
+    let (stable_ms, set_stable_ms) = signal(DEFAULT_STABLE_MS);
+    // What every sticker looked like in the last picture that was recognized here
+    let (sticker_colors, set_sticker_colors) = signal(None::<Vec<StickerRecognition>>);
+    let (show_sticker_colors, set_show_sticker_colors) = signal(false);
 
     let do_pixel_assignment = {
         let playing_barrier = Arc::clone(&playing_barrier);
@@ -106,12 +347,12 @@ pub fn App() -> impl IntoView {
             set_enabled: set_video_enabled,
             ..
         } = use_user_media_return;
-        move || {
+        move |initial: Option<Box<[Pixel]>>| {
             let video_ref = video_ref.get_untracked().unwrap();
             let canvas_ref = canvas_ref.get_untracked().unwrap();
             let playing_barrier = Arc::clone(&playing_barrier);
             spawn_local(async move {
-                let blob = pixel_assignment_command(
+                let image = assignment_image_command(
                     &video_ref,
                     &canvas_ref,
                     video_enabled,
@@ -119,14 +360,11 @@ pub fn App() -> impl IntoView {
                     &playing_barrier,
                 )
                 .await;
-                let form_data = web_sys::FormData::new().unwrap();
-                form_data.append_with_blob("qvis_picture", &blob).unwrap();
-                pixel_assignment_action.dispatch_local(form_data);
+                set_assignment_image.set(Some((image, initial)));
             });
         }
     };
     {
-        let cv_available_tx = cv_available_tx.clone();
         let cv_available_rx = cv_available_rx.clone();
         let playing_barrier = Arc::clone(&playing_barrier);
         let do_pixel_assignment = do_pixel_assignment.clone();
@@ -139,69 +377,155 @@ pub fn App() -> impl IntoView {
 
                 let take_picture_channel = take_picture_channel.clone();
                 let mut cv_available_rx = cv_available_rx.clone();
-                let cv_available_tx = cv_available_tx.clone();
                 let UseUserMediaReturn {
                     enabled: video_enabled,
                     set_enabled: set_video_enabled,
                     ..
                 } = use_user_media_return;
                 match msg {
-                    TakePictureMessage::TakePicture => {
+                    // Another tab was asked
+                    TakePictureMessage::TakePicture(request)
+                    | TakePictureMessage::Calibrate(request, _)
+                        if request.client != client_id() => {}
+                    TakePictureMessage::TakePicture(request) => {
+                        let request = request.id;
                         let playing_barrier = Arc::clone(&playing_barrier);
                         let do_pixel_assignment = do_pixel_assignment.clone();
                         spawn_local(async move {
-                            let pixels = take_picture_command(
+                            let locations = cv_available_rx
+                                .borrow()
+                                .as_ref()
+                                .map(CVProcessor::pixel_assignment_locations);
+                            let waited = wait_until_stable_command(
                                 &video_ref,
                                 &canvas_ref,
                                 video_enabled,
                                 set_video_enabled,
                                 &playing_barrier,
+                                locations.as_deref(),
+                                Duration::from_millis(stable_ms.get_untracked().into()),
+                            )
+                            .await;
+                            let burst = take_burst_command(
+                                &video_ref,
+                                &canvas_ref,
+                                video_enabled,
+                                set_video_enabled,
+                                &playing_barrier,
+                                burst_frames.get_untracked(),
                             )
                             .await;
                             if cv_available_rx.borrow_and_update().is_none() {
-                                do_pixel_assignment();
+                                do_pixel_assignment(None);
                                 cv_available_rx.changed().await.unwrap();
                             }
                             let cv_processor = cv_available_rx.borrow_and_update();
                             let cv_processor = cv_processor.as_ref().unwrap();
-                            let (permutation, confidence) = cv_processor.process_image(&pixels);
+                            let Some(pixels) = combine_burst(cv_processor, burst) else {
+                                take_picture_channel
+                                    .send_message(picture_mismatch(request))
+                                    .unwrap();
+                                return;
+                            };
+                            let (permutation, confidence, stickers) =
+                                cv_processor.recognize_stickers(&pixels);
                             info!("Processed {permutation} with confidence {:.2}", confidence * 100.);
+                            let occluded = occluded_stickers(&stickers);
+                            if !occluded.is_empty() {
+                                warn!("Stickers {occluded:?} were occluded");
+                            }
+                            set_sticker_colors.set(Some(stickers.into_vec()));
                             take_picture_channel
                                 .send_message(TakePictureMessage::PermutationResult(
+                                    request,
                                     permutation,
                                     confidence,
+                                    waited,
+                                    occluded,
                                 ))
                                 .unwrap();
                         });
                     }
-                    TakePictureMessage::Calibrate(permutation) => {
+                    TakePictureMessage::Calibrate(request, permutation) => {
+                        let request = request.id;
                         let permutation = permutation.clone();
                         let playing_barrier = Arc::clone(&playing_barrier);
                         let do_pixel_assignment = do_pixel_assignment.clone();
                         spawn_local(async move {
-                            let pixels = take_picture_command(
+                            let locations = cv_available_rx
+                                .borrow()
+                                .as_ref()
+                                .map(CVProcessor::pixel_assignment_locations);
+                            let waited = wait_until_stable_command(
                                 &video_ref,
                                 &canvas_ref,
                                 video_enabled,
                                 set_video_enabled,
                                 &playing_barrier,
+                                locations.as_deref(),
+                                Duration::from_millis(stable_ms.get_untracked().into()),
+                            )
+                            .await;
+                            let burst = take_burst_command(
+                                &video_ref,
+                                &canvas_ref,
+                                video_enabled,
+                                set_video_enabled,
+                                &playing_barrier,
+                                burst_frames.get_untracked(),
                             )
                             .await;
                             if cv_available_rx.borrow().is_none() {
-                                do_pixel_assignment();
+                                do_pixel_assignment(None);
                                 cv_available_rx.changed().await.unwrap();
                             }
-                            cv_available_tx.send_modify(|maybe_cv_processor| {
-                                let cv_processor = maybe_cv_processor.as_mut().unwrap();
-                                cv_processor.calibrate(&pixels, &permutation);
-                            });
-                            take_picture_channel
-                                .send_message(TakePictureMessage::Calibrated)
-                                .unwrap();
+                            let (pixels, assigned, recognized) = {
+                                let cv_processor = cv_available_rx.borrow();
+                                let cv_processor = cv_processor.as_ref().unwrap();
+                                let Some(pixels) = combine_burst(cv_processor, burst) else {
+                                    take_picture_channel
+                                        .send_message(picture_mismatch(request))
+                                        .unwrap();
+                                    return;
+                                };
+                                // Only pixels that are assigned to stickers calibrate anything, so they are all that the server needs
+                                let assigned = pixels
+                                    .iter()
+                                    .zip(cv_processor.pixel_assignment_locations())
+                                    .enumerate()
+                                    .filter(|(_, (_, assigned))| *assigned)
+                                    .map(|(idx, (pixel, _))| (idx, *pixel))
+                                    .collect::<Vec<_>>();
+                                // Recognizing here keeps the server from doing it while it holds its processor
+                                let recognized = cv_processor.process_image(&pixels).0;
+                                (pixels, assigned, recognized)
+                            };
+                            // The server calibrates its own processor and pushes it back, so that calibrations are never lost to an older processor that is still being shared
+                            let calibration = leptos::serde_json::to_string(&(
+                                request,
+                                &assigned,
+                                &permutation,
+                                &recognized,
+                            ))
+                            .unwrap();
+                            let reply = match calibrate_server_processor(calibration).await {
+                                Ok(()) => {
+                                    calibration_dataset.update_value(|calibration_dataset| {
+                                        calibration_dataset.push(pixels, permutation);
+                                    });
+                                    TakePictureMessage::Calibrated(request, waited)
+                                }
+                                Err(err) => {
+                                    warn!("Failed to calibrate the server's CVProcessor: {err}");
+                                    TakePictureMessage::Failed(request, err.to_string())
+                                }
+                            };
+                            take_picture_channel.send_message(reply).unwrap();
                         });
                     }
-                    m @ (TakePictureMessage::PermutationResult(_, _)
-                    | TakePictureMessage::Calibrated) => {
+                    m @ (TakePictureMessage::PermutationResult(..)
+                    | TakePictureMessage::Calibrated(..)
+                    | TakePictureMessage::Failed(..)) => {
                         warn!("Received {m:?} on client, which should not happen");
                     }
                 }
@@ -209,31 +533,86 @@ pub fn App() -> impl IntoView {
             .unwrap();
     }
 
-    {
+    let install_pixel_assignment = {
         let cv_available_tx = cv_available_tx.clone();
         let cube3 = Arc::clone(&cube3);
-        Effect::new(move |_| {
-            let pixel_assignment = pixel_assignment_action.value().get();
-            let Some(pixel_assignment) = pixel_assignment else {
-                return;
-            };
-            let pixel_assignment = match pixel_assignment {
-                Ok(pixels) => pixels,
-                Err(err) => {
-                    warn!("Pixel assignment failed: {err}");
-                    return;
-                }
-            };
-
-            let cv_processor =
-                CVProcessor::new(Arc::clone(&cube3), pixel_assignment.len(), pixel_assignment);
-
-            info!("0");
+        let share_cv_processor_with_server = share_cv_processor_with_server.clone();
+        move |(pixel_assignment, pixel_weights): (Box<[Pixel]>, Box<[f64]>)| {
+            // Keep the calibration and weights of every sticker whose pixels didn't change
             cv_available_tx.send_modify(|maybe_cv_processor| {
-                *maybe_cv_processor = Some(cv_processor);
+                let old_pixel_assignment =
+                    maybe_cv_processor.as_ref().map(CVProcessor::pixel_assignment);
+                match (maybe_cv_processor.as_mut(), old_pixel_assignment) {
+                    (Some(cv_processor), Some(old_pixel_assignment))
+                        if old_pixel_assignment.len() == pixel_assignment.len() =>
+                    {
+                        let targets = changed_targets(&old_pixel_assignment, &pixel_assignment);
+                        // Without any pictures, recalibrating would forget the calibration of the stale stickers and restore nothing, which happens after a reload or an import
+                        calibration_dataset.with_value(|calibration_dataset| {
+                            cv_processor.reassign(
+                                &pixel_assignment,
+                                &targets,
+                                (!calibration_dataset.is_empty()).then_some(calibration_dataset),
+                            );
+                        });
+                        let mut merged_weights = cv_processor.pixel_weights();
+                        for (i, pixel) in pixel_assignment.iter().enumerate() {
+                            if targets.contains(pixel) {
+                                merged_weights[i] = pixel_weights[i];
+                            }
+                        }
+                        cv_processor.set_pixel_weights(&merged_weights);
+                        info!(
+                            "Reassigned {} stickers and recalibrated them with {} images",
+                            targets.len(),
+                            calibration_dataset.with_value(CalibrationDataset::len)
+                        );
+                    }
+                    _ => {
+                        calibration_dataset.set_value(new_calibration_dataset());
+                        let mut cv_processor = CVProcessor::new(
+                            Arc::clone(&cube3),
+                            pixel_assignment.len(),
+                            pixel_assignment,
+                        );
+                        cv_processor.set_pixel_weights(&pixel_weights);
+                        *maybe_cv_processor = Some(cv_processor);
+                    }
+                }
+                // Pixels were assigned on pictures of the capture width
+                let width = capture_width.get_untracked() as usize;
+                if let Some(cv_processor) = maybe_cv_processor
+                    && cv_processor.image_width().is_none()
+                    && let Err(err) = cv_processor.set_image_width(width)
+                {
+                    warn!("Pictures of this CVProcessor can't be resampled: {err}");
+                }
             });
-        });
-    }
+            share_cv_processor_with_server();
+        }
+    };
+    let pixel_assignment_editor = {
+        let cube3 = Arc::clone(&cube3);
+        move || {
+            let (image, initial) = assignment_image.get()?;
+            let install_pixel_assignment = install_pixel_assignment.clone();
+            Some(view! {
+              <PixelAssignmentEditor
+                image
+                puzzle=Arc::clone(&cube3)
+                initial
+                on_finish=move |pixel_assignment| {
+                  set_assignment_image.set(None);
+                  install_pixel_assignment(pixel_assignment);
+                }
+                on_cancel=move |()| {
+                  warn!("Pixel assignment cancelled");
+                  set_assignment_image.set(None);
+                }
+              />
+            })
+        }
+    };
 
     Effect::watch(
         move || messages.get(),
@@ -249,78 +628,317 @@ pub fn App() -> impl IntoView {
         false,
     );
 
-    let cv_available_rx2 = cv_available_rx.clone();
-    let do_export_cv_processor = move |_| {
-        let export_file_name = match web_sys::window().unwrap().prompt_with_message_and_default(
-            "Enter file name for CVProcessor export",
-            "cv_processor_export.json",
-        ) {
-            Ok(Some(export_file_name)) if !export_file_name.trim().is_empty() => export_file_name,
-            Ok(Some(_)) => {
-                warn!("Export cancelled: file name is empty");
-                return;
+    let (profiles, set_profiles) = signal(None::<(Option<String>, Vec<ProfileInfo>)>);
+    let (profile_name, set_profile_name) = signal(String::new());
+    let refresh_profiles = move || {
+        spawn_local(async move {
+            match list_profiles().await {
+                Ok(list) => set_profiles.set(Some(list)),
+                Err(err) => warn!("Failed to list profiles: {err}"),
             }
-            Ok(None) => {
-                warn!("Export cancelled: user cancelled dialog");
-                return;
+        });
+    };
+    let do_toggle_profiles = move |_| {
+        if profiles.get_untracked().is_some() {
+            set_profiles.set(None);
+        } else {
+            refresh_profiles();
+        }
+    };
+
+    let do_save_profile = move |_| {
+        let name = profile_name.get_untracked().trim().to_owned();
+        if name.is_empty() {
+            warn!("Save cancelled: name is empty");
+            return;
+        }
+        spawn_local(async move {
+            if let Err(err) = save_profile(name.clone(), camera_settings.get_untracked()).await {
+                warn!("Failed to save profile: {err}");
+            } else {
+                info!("Successfully saved profile {name}");
+                camera_settings_profile.set_value(Some(name));
+                refresh_profiles();
             }
-            Err(err) => {
-                warn!("Export cancelled: prompt failed: {err:?}");
-                return;
+        });
+    };
+
+    let do_select_profile = move |name: String| {
+        spawn_local(async move {
+            // The server pushes the selected processor to every client
+            match select_profile(name.clone()).await {
+                Ok(()) => {
+                    calibration_dataset.set_value(new_calibration_dataset());
+                    info!("Successfully selected profile {name}");
+                    refresh_profiles();
+                }
+                Err(err) => {
+                    warn!("Failed to select profile: {err}");
+                }
             }
+        });
+    };
+
+    let do_upload_profile = move |input: web_sys::HtmlInputElement| {
+        let Some(file) = input.files().and_then(|files| files.get(0)) else {
+            return;
         };
-        let cv_available_rx2 = cv_available_rx2.borrow();
-        let Some(cv_processor2) = cv_available_rx2.as_ref().cloned() else {
-            warn!("Export failed: CVProcessor not yet available");
+        // Lets the same file be uploaded again
+        input.set_value("");
+        let form_data = web_sys::FormData::new().unwrap();
+        form_data
+            .append_with_str("qvis_name", profile_name.get_untracked().trim())
+            .unwrap();
+        form_data
+            .append_with_blob_and_filename("qvis_cv_processor", &file, &file.name())
+            .unwrap();
+        spawn_local(async move {
+            match upload_cv_processor(form_data.into()).await {
+                Ok(name) => {
+                    calibration_dataset.set_value(new_calibration_dataset());
+                    info!("Successfully uploaded CVProcessor as profile {name}");
+                    refresh_profiles();
+                }
+                Err(err) => {
+                    warn!("Failed to upload CVProcessor: {err}");
+                }
+            }
+        });
+    };
+
+    let do_clone_profile = move |name: String| {
+        let Some(new_name) = prompt_for("Enter name for the copy", &format!("{name}_copy")) else {
             return;
         };
+        spawn_local(async move {
+            if let Err(err) = clone_profile(name.clone(), new_name.clone()).await {
+                warn!("Failed to clone profile: {err}");
+            } else {
+                info!("Successfully cloned profile {name} to {new_name}");
+                refresh_profiles();
+            }
+        });
+    };
 
-        let cv_processor2 = leptos::serde_json::to_string(&cv_processor2).unwrap();
+    let do_rename_profile = move |name: String| {
+        let Some(new_name) = prompt_for("Enter new name", &name) else {
+            return;
+        };
         spawn_local(async move {
-            if let Err(err) = export_cv_processor(cv_processor2, export_file_name.clone()).await {
-                warn!("Failed to export CVProcessor: {err}");
+            if let Err(err) = rename_profile(name.clone(), new_name.clone()).await {
+                warn!("Failed to rename profile: {err}");
             } else {
-                info!("Successfully exported CVProcessor to {export_file_name}");
+                info!("Successfully renamed profile {name} to {new_name}");
+                refresh_profiles();
             }
         });
     };
 
-    let do_import_cv_processor = move |_| {
-        let export_file_name = match web_sys::window().unwrap().prompt_with_message_and_default(
-            "Enter file name for CVProcessor import",
-            "cv_processor_export.json",
-        ) {
-            Ok(Some(export_file_name)) if !export_file_name.trim().is_empty() => export_file_name,
-            Ok(Some(_)) => {
-                warn!("Import cancelled: file name is empty");
-                return;
+    let do_edit_profile_tags = move |name: String, tags: &[String]| {
+        let Some(tags) = prompt_for("Enter comma separated tags", &tags.join(", ")) else {
+            return;
+        };
+        let tags = tags.split(',').map(str::to_owned).collect();
+        spawn_local(async move {
+            if let Err(err) = set_profile_tags(name, tags).await {
+                warn!("Failed to set profile tags: {err}");
+            } else {
+                refresh_profiles();
             }
-            Ok(None) => {
-                warn!("Import cancelled: user cancelled dialog");
-                return;
+        });
+    };
+
+    let do_delete_profile = move |name: String| {
+        let confirmed = web_sys::window()
+            .unwrap()
+            .confirm_with_message(&format!("Delete profile {name}?"))
+            .unwrap_or(false);
+        if !confirmed {
+            return;
+        }
+        spawn_local(async move {
+            if let Err(err) = delete_profile(name.clone()).await {
+                warn!("Failed to delete profile: {err}");
+            } else {
+                info!("Successfully deleted profile {name}");
+                refresh_profiles();
             }
-            Err(err) => {
-                warn!("Import cancelled: prompt failed: {err:?}");
+        });
+    };
+
+    let do_new_pixel_assignment = {
+        let do_pixel_assignment = do_pixel_assignment.clone();
+        move |_| do_pixel_assignment(None)
+    };
+
+    let cv_available_rx3 = cv_available_rx.clone();
+    let do_edit_pixel_assignment = {
+        let do_pixel_assignment = do_pixel_assignment.clone();
+        move |_| {
+            let Some(initial) = cv_available_rx3
+                .borrow()
+                .as_ref()
+                .map(CVProcessor::pixel_assignment)
+            else {
+                warn!("Edit failed: CVProcessor not yet available");
                 return;
+            };
+            do_pixel_assignment(Some(initial));
+        }
+    };
+
+    let (pixel_assignments, set_pixel_assignments) = signal(None::<Vec<String>>);
+    let (pixel_assignment_name, set_pixel_assignment_name) = signal("pixel_assignment".to_owned());
+    let refresh_pixel_assignments = move || {
+        spawn_local(async move {
+            match list_models(ModelKind::PixelAssignment).await {
+                Ok(list) => set_pixel_assignments.set(Some(list)),
+                Err(err) => warn!("Failed to list pixel assignments: {err}"),
             }
+        });
+    };
+    let do_toggle_pixel_assignments = move |_| {
+        if pixel_assignments.get_untracked().is_some() {
+            set_pixel_assignments.set(None);
+        } else {
+            refresh_pixel_assignments();
+        }
+    };
+
+    let cv_available_rx4 = cv_available_rx.clone();
+    let do_export_pixel_assignment = move |_| {
+        let export_name = pixel_assignment_name.get_untracked().trim().to_owned();
+        if export_name.is_empty() {
+            warn!("Export cancelled: name is empty");
+            return;
+        }
+        let Some(pixel_assignment) = cv_available_rx4.borrow().as_ref().map(|cv_processor| {
+            // The canvas may have been resized since the pixels were assigned
+            let width = cv_processor.image_width().unwrap_or(DEFAULT_WIDTH as usize);
+            leptos::serde_json::to_string(&(cv_processor.pixel_assignment(), width)).unwrap()
+        }) else {
+            warn!("Export failed: CVProcessor not yet available");
+            return;
         };
 
-        let cv_available_tx = cv_available_tx.clone();
         spawn_local(async move {
-            match import_cv_processor(export_file_name.clone()).await {
-                Ok(cv_processor) => {
-                    cv_available_tx.send_modify(|maybe_cv_processor| {
-                        *maybe_cv_processor = Some(cv_processor);
-                    });
-                    info!("Successfully imported CVProcessor from {export_file_name}");
+            if let Err(err) = export_pixel_assignment(pixel_assignment, export_name.clone()).await {
+                warn!("Failed to export pixel assignment: {err}");
+            } else {
+                info!("Successfully exported pixel assignment to {export_name}");
+                refresh_pixel_assignments();
+            }
+        });
+    };
+
+    let do_import_pixel_assignment = {
+        let do_pixel_assignment = do_pixel_assignment.clone();
+        move |import_name: String| {
+            let do_pixel_assignment = do_pixel_assignment.clone();
+            spawn_local(async move {
+                match import_pixel_assignment(import_name.clone()).await {
+                    Ok((pixel_assignment, width)) => {
+                        let canvas_width = canvas_ref.get_untracked().unwrap().width() as usize;
+                        if width != canvas_width {
+                            warn!(
+                                "Imported pixel assignment is {width} pixels wide but pictures are {canvas_width} pixels wide"
+                            );
+                            return;
+                        }
+                        info!("Successfully imported pixel assignment from {import_name}");
+                        do_pixel_assignment(Some(pixel_assignment));
+                    }
+                    Err(err) => {
+                        warn!("Failed to import pixel assignment: {err}");
+                    }
+                }
+            });
+        }
+    };
+
+    let do_delete_pixel_assignment = move |name: String| {
+        let confirmed = web_sys::window()
+            .unwrap()
+            .confirm_with_message(&format!("Delete pixel assignment {name}?"))
+            .unwrap_or(false);
+        if !confirmed {
+            return;
+        }
+        spawn_local(async move {
+            if let Err(err) = delete_model(ModelKind::PixelAssignment, name.clone()).await {
+                warn!("Failed to delete pixel assignment: {err}");
+            } else {
+                info!("Successfully deleted pixel assignment {name}");
+                refresh_pixel_assignments();
+            }
+        });
+    };
+
+    let (checkpoints, set_checkpoints) = signal(None::<Vec<CheckpointInfo>>);
+    let do_toggle_checkpoints = move |_| {
+        if checkpoints.get_untracked().is_some() {
+            set_checkpoints.set(None);
+            return;
+        }
+        spawn_local(async move {
+            match list_checkpoints().await {
+                Ok(list) => set_checkpoints.set(Some(list)),
+                Err(err) => warn!("Failed to list checkpoints: {err}"),
+            }
+        });
+    };
+    let do_restore_checkpoint = move |name: String| {
+        spawn_local(async move {
+            // The server pushes the restored processor to every client
+            match restore_checkpoint(name.clone()).await {
+                Ok(()) => {
+                    calibration_dataset.set_value(new_calibration_dataset());
+                    set_checkpoints.set(None);
+                    info!("Successfully restored checkpoint {name}");
                 }
                 Err(err) => {
-                    warn!("Failed to import CVProcessor: {err}");
+                    warn!("Failed to restore checkpoint: {err}");
                 }
             }
         });
     };
 
+    let do_recognize_stickers = {
+        let playing_barrier = Arc::clone(&playing_barrier);
+        let cv_available_rx = cv_available_rx.clone();
+        let UseUserMediaReturn {
+            enabled: video_enabled,
+            set_enabled: set_video_enabled,
+            ..
+        } = use_user_media_return;
+        move |_| {
+            let playing_barrier = Arc::clone(&playing_barrier);
+            let cv_available_rx = cv_available_rx.clone();
+            spawn_local(async move {
+                let burst = take_burst_command(
+                    &video_ref.get_untracked().unwrap(),
+                    &canvas_ref.get_untracked().unwrap(),
+                    video_enabled,
+                    set_video_enabled,
+                    &playing_barrier,
+                    burst_frames.get_untracked(),
+                )
+                .await;
+                let Some(cv_processor) = &*cv_available_rx.borrow() else {
+                    warn!("Recognition cancelled: there is no CVProcessor yet");
+                    return;
+                };
+                let Some(pixels) = combine_burst(cv_processor, burst) else {
+                    warn!("Recognition cancelled: the picture doesn't fit the CVProcessor");
+                    return;
+                };
+                let (permutation, confidence, stickers) = cv_processor.recognize_stickers(&pixels);
+                info!("Processed {permutation} with confidence {:.2}", confidence * 100.);
+                set_sticker_colors.set(Some(stickers.into_vec()));
+            });
+        }
+    };
+
     view! {
       <header class="font-sans text-4xl font-bold tracking-wider text-center bg-[rgb(47,48,80)] leading-20">
         <button
@@ -333,27 +951,363 @@ pub fn App() -> impl IntoView {
         </button>
       </header>
       <main class="flex flex-col gap-4 justify-center mt-5 mr-4 mb-6 ml-4 text-center">
-        <Video video_ref canvas_ref cv_overlay_ref use_user_media_return playing_barrier cv_available_rx />
-        // zoom
-        // resolution (width)
-        // camera device
-        <div class="flex h-12">
-          <button on:click=move |_| do_pixel_assignment() class="flex-1 border-2 border-white cursor-pointer">
-            {move || {
-              if pixel_assignment_action.pending().get() {
-                "Processing...".to_string()
-              } else {
-                "Pixel assignment".to_string()
+        <Video
+          video_ref
+          canvas_ref
+          cv_overlay_ref
+          use_user_media_return
+          playing_barrier
+          cv_available_rx
+          live_recognition
+          camera_settings
+          capture_width
+        />
+        <label class="flex gap-2 justify-center items-center">
+          "Capture width"
+          <input
+            type="number"
+            min="1"
+            class="px-2 w-24 text-black"
+            prop:value=move || capture_width.get().to_string()
+            on:change:target=move |ev| {
+              if let Ok(width) = ev.target().value().parse::<u32>() && width > 0 {
+                capture_width.set(width);
               }
-            }}
+            }
+          />
+          "pixels, for new pixel assignments"
+        </label>
+        <div class="flex h-12">
+          <button on:click=do_new_pixel_assignment class="flex-1 border-2 border-white cursor-pointer">
+            "Pixel assignment"
+          </button>
+          <button class="flex-1 border-2 border-white cursor-pointer" on:click=do_edit_pixel_assignment>
+            "Edit assignment"
+          </button>
+          <button class="flex-1 border-2 border-white cursor-pointer" on:click=do_toggle_pixel_assignments>
+            "Saved assignments"
           </button>
-          <button class="flex-1 border-2 border-white cursor-pointer" on:click=do_export_cv_processor>
-            "Export CVProcessor"
+          <button class="flex-1 border-2 border-white cursor-pointer" on:click=do_toggle_profiles>
+            "Profiles"
           </button>
-          <button class="flex-1 border-2 border-white cursor-pointer" on:click=do_import_cv_processor>
-            "Import CVProcessor"
+          <button class="flex-1 border-2 border-white cursor-pointer" on:click=do_toggle_checkpoints>
+            "Checkpoints"
+          </button>
+          <button
+            class="flex-1 border-2 border-white cursor-pointer"
+            on:click=move |_| set_show_sticker_colors.update(|show| *show = !*show)
+          >
+            "Sticker colors"
           </button>
         </div>
+        {move || {
+          show_sticker_colors
+            .get()
+            .then(|| {
+              view! {
+                <div class="flex flex-col gap-2 p-2 border-2 border-white">
+                  <button class="px-2 border-2 border-white cursor-pointer" on:click=do_recognize_stickers.clone()>
+                    "Recognize picture"
+                  </button>
+                  {move || {
+                    let Some(stickers) = sticker_colors.get() else {
+                      return view! { <p>"No picture recognized yet"</p> }.into_any();
+                    };
+                    let disagreeing = stickers.iter().filter(|sticker| sticker.disagrees()).count();
+                    let occluded = occluded_stickers(&stickers).len();
+                    view! {
+                      <p>
+                        {format!(
+                          "The matched state disagrees with {disagreeing} stickers, {occluded} stickers were occluded",
+                        )}
+                      </p>
+                      <table class="w-full text-left">
+                        <thead>
+                          <tr>
+                            <th>"Sticker"</th>
+                            <th>"Matched"</th>
+                            <th>"Colors"</th>
+                          </tr>
+                        </thead>
+                        <tbody>
+                          {stickers
+                            .into_iter()
+                            .enumerate()
+                            .map(|(sticker, recognition)| {
+                              view! {
+                                <tr class:bg-red-900=recognition.disagrees() class:text-gray-400=recognition.occluded>
+                                  <td>{sticker}</td>
+                                  <td>{recognition.matched_color.to_string()}</td>
+                                  <td>
+                                    {if recognition.occluded {
+                                      "Occluded".to_string()
+                                    } else {
+                                      format_probabilities(&recognition.probabilities)
+                                    }}
+                                  </td>
+                                </tr>
+                              }
+                            })
+                            .collect_view()}
+                        </tbody>
+                      </table>
+                    }
+                      .into_any()
+                  }}
+                </div>
+              }
+            })
+        }}
+        {move || {
+          pixel_assignments
+            .get()
+            .map(|names| {
+              view! {
+                <div class="flex flex-col gap-2 p-2 border-2 border-white">
+                  <div class="flex gap-2 items-center">
+                    <input
+                      type="text"
+                      placeholder="Name"
+                      class="flex-1 px-2 text-black"
+                      prop:value=move || pixel_assignment_name.get()
+                      on:input:target=move |ev| set_pixel_assignment_name.set(ev.target().value())
+                    />
+                    <button class="px-2 border-2 border-white cursor-pointer" on:click=do_export_pixel_assignment.clone()>
+                      "Save current"
+                    </button>
+                  </div>
+                  {names.is_empty().then(|| view! { <p>"No saved pixel assignments yet"</p> })}
+                  <table class="w-full text-left">
+                    <tbody>
+                      {names
+                        .into_iter()
+                        .map(|name| {
+                          let do_import_pixel_assignment = do_import_pixel_assignment.clone();
+                          view! {
+                            <tr>
+                              <td>{name.clone()}</td>
+                              <td class="flex gap-2">
+                                <button
+                                  class="px-2 border-2 border-white cursor-pointer"
+                                  on:click={
+                                    let name = name.clone();
+                                    move |_| do_import_pixel_assignment(name.clone())
+                                  }
+                                >
+                                  "Load"
+                                </button>
+                                <button
+                                  class="px-2 border-2 border-white cursor-pointer"
+                                  on:click=move |_| do_delete_pixel_assignment(name.clone())
+                                >
+                                  "Delete"
+                                </button>
+                              </td>
+                            </tr>
+                          }
+                        })
+                        .collect_view()}
+                    </tbody>
+                  </table>
+                </div>
+              }
+            })
+        }}
+        {move || {
+          profiles
+            .get()
+            .map(|(active, profiles)| {
+              view! {
+                <div class="flex flex-col gap-2 p-2 border-2 border-white">
+                  <div class="flex gap-2 items-center">
+                    <input
+                      type="text"
+                      placeholder="Name"
+                      class="flex-1 px-2 text-black"
+                      prop:value=move || profile_name.get()
+                      on:input:target=move |ev| set_profile_name.set(ev.target().value())
+                    />
+                    <button class="px-2 border-2 border-white cursor-pointer" on:click=do_save_profile>
+                      "Save current"
+                    </button>
+                    <label class="px-2 border-2 border-white cursor-pointer">
+                      "Upload"
+                      <input
+                        type="file"
+                        accept=".json,application/json"
+                        class="hidden"
+                        on:change:target=move |ev| do_upload_profile(ev.target())
+                      />
+                    </label>
+                  </div>
+                  <table class="w-full text-left">
+                    <thead>
+                      <tr>
+                        <th>"Name"</th>
+                        <th>"Puzzle"</th>
+                        <th>"Resolution"</th>
+                        <th>"Created"</th>
+                        <th>"Calibrations"</th>
+                        <th>"Accuracy"</th>
+                        <th>"Tags"</th>
+                        <th />
+                      </tr>
+                    </thead>
+                    <tbody>
+                      {profiles
+                        .into_iter()
+                        .map(|profile| {
+                          let is_active = active.as_ref() == Some(&profile.name);
+                          let name = profile.name.clone();
+                          let tags = profile.tags.clone();
+                          view! {
+                            <tr class:font-bold=is_active>
+                              <td>{profile.name.clone()}</td>
+                              <td>{profile.puzzle.clone()}</td>
+                              <td>{format!("{}x{}", profile.resolution.0, profile.resolution.1)}</td>
+                              <td>{format_date(profile.created)}</td>
+                              <td>{profile.calibrations}</td>
+                              <td>
+                                {profile
+                                  .accuracy
+                                  .map_or_else(
+                                    || "-".to_string(),
+                                    |accuracy| format!("{:.1}%", accuracy * 100.),
+                                  )}
+                              </td>
+                              <td>{profile.tags.join(", ")}</td>
+                              <td class="flex gap-2">
+                                <button
+                                  class="px-2 border-2 border-white cursor-pointer"
+                                  on:click={
+                                    let name = name.clone();
+                                    move |_| do_select_profile(name.clone())
+                                  }
+                                >
+                                  "Select"
+                                </button>
+                                <button
+                                  class="px-2 border-2 border-white cursor-pointer"
+                                  on:click={
+                                    let name = name.clone();
+                                    move |_| do_clone_profile(name.clone())
+                                  }
+                                >
+                                  "Clone"
+                                </button>
+                                <button
+                                  class="px-2 border-2 border-white cursor-pointer"
+                                  on:click={
+                                    let name = name.clone();
+                                    move |_| do_rename_profile(name.clone())
+                                  }
+                                >
+                                  "Rename"
+                                </button>
+                                <button
+                                  class="px-2 border-2 border-white cursor-pointer"
+                                  on:click={
+                                    let name = name.clone();
+                                    move |_| do_edit_profile_tags(name.clone(), &tags)
+                                  }
+                                >
+                                  "Tags"
+                                </button>
+                                <a class="px-2 border-2 border-white" href=cv_processor_download_url(&name) download>
+                                  "Download"
+                                </a>
+                                <button
+                                  class="px-2 border-2 border-white cursor-pointer"
+                                  on:click=move |_| do_delete_profile(name.clone())
+                                >
+                                  "Delete"
+                                </button>
+                              </td>
+                            </tr>
+                          }
+                        })
+                        .collect_view()}
+                    </tbody>
+                  </table>
+                </div>
+              }
+            })
+        }}
+        {move || {
+          checkpoints
+            .get()
+            .map(|checkpoints| {
+              if checkpoints.is_empty() {
+                return view! { <p>"No checkpoints yet"</p> }.into_any();
+              }
+              view! {
+                <ul class="flex flex-col gap-1 p-2 border-2 border-white">
+                  {checkpoints
+                    .into_iter()
+                    .map(|checkpoint| {
+                      let name = checkpoint.name;
+                      view! {
+                        <li class="flex gap-4 justify-between items-center">
+                          {format!(
+                            "{} calibrations, {} ago",
+                            checkpoint.calibrations,
+                            format_age(checkpoint.age_secs),
+                          )}
+                          <button
+                            class="px-2 border-2 border-white cursor-pointer"
+                            on:click=move |_| do_restore_checkpoint(name.clone())
+                          >
+                            "Restore"
+                          </button>
+                        </li>
+                      }
+                    })
+                    .collect_view()}
+                </ul>
+              }
+                .into_any()
+            })
+        }}
+        <label class="flex gap-2 justify-center items-center">
+          <input
+            type="checkbox"
+            prop:checked=move || live_recognition.get()
+            on:change:target=move |ev| set_live_recognition.set(ev.target().checked())
+          />
+          "Live recognition"
+        </label>
+        <label class="flex gap-2 justify-center items-center">
+          "Average"
+          <input
+            type="number"
+            min="1"
+            max="30"
+            class="px-2 w-16 text-black"
+            prop:value=move || burst_frames.get().to_string()
+            on:change:target=move |ev| {
+              if let Ok(frames) = ev.target().value().parse::<usize>() && frames > 0 {
+                set_burst_frames.set(frames);
+              }
+            }
+          />
+          "frames per picture"
+        </label>
+        <label class="flex gap-2 justify-center items-center">
+          "Wait for the puzzle to hold still for"
+          <input
+            type="number"
+            min="0"
+            step="50"
+            class="px-2 w-20 text-black"
+            prop:value=move || stable_ms.get().to_string()
+            on:change:target=move |ev| {
+              if let Ok(ms) = ev.target().value().parse::<u32>() {
+                set_stable_ms.set(ms);
+              }
+            }
+          />
+          "ms before robot pictures"
+        </label>
         "Messages:"
         <div class="relative h-72 font-mono text-left border-2 border-gray-300">
           <div
@@ -372,58 +1326,293 @@ pub fn App() -> impl IntoView {
           </div>
         </div>
       </main>
+      {pixel_assignment_editor}
     }
 }
 
 #[server]
-async fn print_ready() -> Result<(), ServerFnError> {
+async fn print_ready(client: ClientId) -> Result<(), ServerFnError> {
+    use crate::{client_presence::ClientPresence, server_vision::ServerProcessor};
+
+    use_context::<ClientPresence>().unwrap().connected(client);
+    // Give the client the processor, which it lost if it reloaded
+    let cv_processor = use_context::<ServerProcessor>().unwrap().0.borrow().clone();
+    if let Some(cv_processor) = cv_processor {
+        let mut server_signals = use_context::<leptos_ws::WsSignals>().unwrap();
+        ChannelSignal::new_with_context(&mut server_signals, CV_PROCESSOR_CHANNEL)
+            .map_err(ServerFnError::new)?
+            .send_message(CVProcessorMessage(cv_processor))
+            .map_err(ServerFnError::new)?;
+    }
     leptos::logging::log!("READY");
     Ok(())
 }
 
 #[server]
-async fn export_cv_processor(
-    cv_processor: String,
-    export_file_name: String,
-) -> Result<(), ServerFnError> {
+async fn client_heartbeat(client: ClientId) -> Result<(), ServerFnError> {
+    use crate::client_presence::ClientPresence;
+
+    use_context::<ClientPresence>().unwrap().heartbeat(client);
+    Ok(())
+}
+
+#[server]
+async fn share_cv_processor(cv_processor: String) -> Result<(), ServerFnError> {
+    use crate::server_vision::ServerProcessor;
+
     let cv_processor: CVProcessor = leptos::serde_json::from_str(&cv_processor)?;
-    let export_path = std::env::current_dir().unwrap().join(&export_file_name);
-    let export_file = std::fs::File::create(export_path)?;
-    leptos::serde_json::to_writer(export_file, &cv_processor)?;
-    leptos::logging::log!("Exported CVProcessor to {export_file_name}");
+    use_context::<ServerProcessor>().unwrap().set(cv_processor);
     Ok(())
 }
 
+/// Calibrates the server's processor with a picture that a client took for a robot request, given as the JSON of the request id, the indices and colors of the pixels that are assigned to stickers, the permutation that the puzzle was in and the permutation that the client recognized. The server then pushes the calibrated processor to every client.
 #[server]
-async fn import_cv_processor(import_file_name: String) -> Result<CVProcessor, ServerFnError> {
-    let import_path = std::env::current_dir().unwrap().join(&import_file_name);
-    let import_file = std::fs::File::open(import_path)?;
-    let cv_processor = leptos::serde_json::from_reader(import_file)?;
-    leptos::logging::log!("Imported CVProcessor from {import_file_name}");
-    Ok(cv_processor)
+async fn calibrate_server_processor(calibration: String) -> Result<(), ServerFnError> {
+    use crate::server_vision::ServerProcessor;
+
+    type Calibration = (RequestId, Vec<(usize, (f64, f64, f64))>, Permutation, Permutation);
+    let (request, assigned, permutation, recognized): Calibration =
+        leptos::serde_json::from_str(&calibration)?;
+    if use_context::<ServerProcessor>().unwrap().calibrate_assigned_pixels(
+        request,
+        &assigned,
+        &permutation,
+        &recognized,
+    ) {
+        Ok(())
+    } else {
+        Err(ServerFnError::new("The server has no CVProcessor that the picture fits"))
+    }
 }
 
+/// Recognizes the state of the puzzle in an encoded picture using the server's processor, for clients that push pictures instead of recognizing them themselves
 #[server(
     input = MultipartFormData,
 )]
-async fn pixel_assignment(data: MultipartData) -> Result<Box<[Pixel]>, ServerFnError> {
+pub async fn recognize_picture(data: MultipartData) -> Result<(Permutation, f64), ServerFnError> {
+    use crate::server_vision::{ServerProcessor, decode_picture};
+
     let mut data = data.into_inner().unwrap();
-    let field = data
-        .next_field()
-        .await
-        .map_err(ServerFnError::new)?
-        .unwrap();
-    let bytes = field.bytes().await?;
-
-    let pixel_assignment_ui_tx = use_context::<
-        std::sync::mpsc::Sender<(tokio::sync::oneshot::Sender<Box<[Pixel]>>, bytes::Bytes)>,
-    >()
-    .unwrap();
-    let (pixel_assignment_done_tx, pixel_assignment_done_rx) = tokio::sync::oneshot::channel();
-    pixel_assignment_ui_tx
-        .send((pixel_assignment_done_tx, bytes))
-        .unwrap();
-    let pixel_assignment = pixel_assignment_done_rx.await.unwrap();
+    let mut picture = None;
+    while let Some(field) = data.next_field().await? {
+        if field.name() == Some("qvis_picture") {
+            picture = Some(field.bytes().await?);
+        }
+    }
+    let picture = picture.ok_or_else(|| ServerFnError::new("Missing qvis_picture"))?;
+
+    let server_processor = use_context::<ServerProcessor>().unwrap();
+    let recognized = tokio::task::spawn_blocking(move || {
+        let picture = decode_picture(&picture)?;
+        server_processor.process(&picture)
+    })
+    .await?
+    .map_err(|e| ServerFnError::new(e.message))?;
+    let (permutation, confidence, occluded) = recognized
+        .ok_or_else(|| ServerFnError::new("The server has no CVProcessor yet"))?;
+    leptos::logging::log!(
+        "Recognized {permutation} with confidence {:.2}, stickers {occluded:?} were occluded",
+        confidence * 100.
+    );
+    Ok((permutation, confidence))
+}
+
+/// Runs `change` on the blocking pool, since changes to the registry read and write files
+#[cfg(feature = "ssr")]
+async fn change_registry<T: Send + 'static>(
+    change: impl FnOnce(&crate::registry::Registry) -> std::io::Result<T> + Send + 'static,
+) -> Result<T, ServerFnError> {
+    let registry = use_context::<crate::registry::Registry>().unwrap();
+    Ok(tokio::task::spawn_blocking(move || change(&registry)).await??)
+}
+
+#[server]
+async fn list_profiles() -> Result<(Option<String>, Vec<ProfileInfo>), ServerFnError> {
+    Ok(use_context::<crate::registry::Registry>().unwrap().list())
+}
+
+/// Saves the server's processor and the given camera settings as the profile with the given name and makes it the active profile
+#[server]
+async fn save_profile(name: String, camera: CameraSettings) -> Result<(), ServerFnError> {
+    let cv_processor = use_context::<crate::server_vision::ServerProcessor>()
+        .unwrap()
+        .0
+        .borrow()
+        .clone()
+        .ok_or_else(|| ServerFnError::new("The server has no CVProcessor yet"))?;
+    let log_name = name.clone();
+    change_registry(move |registry| {
+        registry.save(&name, &cv_processor)?;
+        registry.set_camera(&name, camera)?;
+        registry.activate(&name)
+    })
+    .await?;
+    leptos::logging::log!("Saved profile {log_name}");
+    Ok(())
+}
+
+/// Returns the name and camera settings of the active profile, if there is one
+#[server]
+async fn active_camera_settings() -> Result<Option<(String, CameraSettings)>, ServerFnError> {
+    Ok(use_context::<crate::registry::Registry>()
+        .unwrap()
+        .active_camera())
+}
+
+/// Replaces the server's processor with the one of the given profile, which the server then pushes to every client
+#[server]
+async fn select_profile(name: String) -> Result<(), ServerFnError> {
+    let selected = name.clone();
+    let cv_processor = change_registry(move |registry| registry.select(&selected)).await?;
+    use_context::<crate::server_vision::ServerProcessor>()
+        .unwrap()
+        .set(cv_processor);
+    leptos::logging::log!("Selected profile {name}");
+    Ok(())
+}
+
+/// Saves a processor that was uploaded from the browser as a profile and selects it. It is saved under the `qvis_name` field, or the name of the uploaded file if that is empty.
+#[server(
+    input = MultipartFormData,
+)]
+async fn upload_cv_processor(data: MultipartData) -> Result<String, ServerFnError> {
+    let mut data = data.into_inner().unwrap();
+    let mut name = None;
+    let mut cv_processor = None;
+    while let Some(field) = data.next_field().await? {
+        match field.name() {
+            Some("qvis_name") => {
+                let text = field.text().await?;
+                if !text.trim().is_empty() {
+                    name = Some(text.trim().to_owned());
+                }
+            }
+            Some("qvis_cv_processor") => {
+                if name.is_none() {
+                    name = field
+                        .file_name()
+                        .map(|file_name| file_name.trim_end_matches(".json").to_owned());
+                }
+                let bytes = field.bytes().await?;
+                cv_processor = Some(leptos::serde_json::from_slice::<CVProcessor>(&bytes)?);
+            }
+            _ => {}
+        }
+    }
+    let cv_processor =
+        cv_processor.ok_or_else(|| ServerFnError::new("Missing qvis_cv_processor"))?;
+    let name = name.ok_or_else(|| ServerFnError::new("Missing qvis_name"))?;
+
+    let cv_processor = {
+        let name = name.clone();
+        change_registry(move |registry| {
+            registry.save(&name, &cv_processor)?;
+            registry.activate(&name)?;
+            Ok(cv_processor)
+        })
+        .await?
+    };
+    use_context::<crate::server_vision::ServerProcessor>()
+        .unwrap()
+        .set(cv_processor);
+    leptos::logging::log!("Uploaded CVProcessor as profile {name}");
+    Ok(name)
+}
+
+#[server]
+async fn clone_profile(name: String, new_name: String) -> Result<(), ServerFnError> {
+    let (from, to) = (name.clone(), new_name.clone());
+    change_registry(move |registry| registry.duplicate(&from, &to)).await?;
+    leptos::logging::log!("Cloned profile {name} to {new_name}");
+    Ok(())
+}
+
+#[server]
+async fn rename_profile(name: String, new_name: String) -> Result<(), ServerFnError> {
+    let (from, to) = (name.clone(), new_name.clone());
+    change_registry(move |registry| registry.rename(&from, &to)).await?;
+    leptos::logging::log!("Renamed profile {name} to {new_name}");
+    Ok(())
+}
+
+#[server]
+async fn set_profile_tags(name: String, tags: Vec<String>) -> Result<(), ServerFnError> {
+    change_registry(move |registry| registry.set_tags(&name, tags)).await?;
+    Ok(())
+}
 
+#[server]
+async fn delete_profile(name: String) -> Result<(), ServerFnError> {
+    let deleted = name.clone();
+    change_registry(move |registry| registry.delete(&deleted)).await?;
+    leptos::logging::log!("Deleted profile {name}");
+    Ok(())
+}
+
+#[server]
+async fn list_models(kind: ModelKind) -> Result<Vec<String>, ServerFnError> {
+    Ok(tokio::task::spawn_blocking(move || crate::storage::list_models(kind)).await??)
+}
+
+/// Deletes the thing of the given kind saved under the given name. Processors are deleted through the registry, so that their profile goes with them.
+#[server]
+async fn delete_model(kind: ModelKind, name: String) -> Result<(), ServerFnError> {
+    let deleted = name.clone();
+    match kind {
+        ModelKind::CVProcessor => {
+            change_registry(move |registry| registry.delete(&deleted)).await?;
+        }
+        ModelKind::PixelAssignment => {
+            tokio::task::spawn_blocking(move || crate::storage::delete_model(kind, &deleted))
+                .await??;
+        }
+    }
+    leptos::logging::log!("Deleted {kind:?} {name}");
+    Ok(())
+}
+
+#[server]
+async fn list_checkpoints() -> Result<Vec<CheckpointInfo>, ServerFnError> {
+    Ok(crate::storage::list_checkpoints()?)
+}
+
+#[server]
+async fn restore_checkpoint(name: String) -> Result<(), ServerFnError> {
+    let cv_processor = crate::storage::load_checkpoint(&name)?;
+    use_context::<crate::server_vision::ServerProcessor>()
+        .unwrap()
+        .set(cv_processor);
+    leptos::logging::log!("Restored checkpoint {name}");
+    Ok(())
+}
+
+/// Saves a pixel assignment under the given name, given as the JSON of the pixels and the width of the pictures that they were assigned on
+#[server]
+async fn export_pixel_assignment(
+    pixel_assignment: String,
+    export_name: String,
+) -> Result<(), ServerFnError> {
+    let (pixel_assignment, width): (Box<[Pixel]>, usize) =
+        leptos::serde_json::from_str(&pixel_assignment)?;
+    let export_path = crate::storage::model_path(ModelKind::PixelAssignment, &export_name)?;
+    tokio::task::spawn_blocking(move || {
+        if let Some(parent) = export_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        crate::storage::save_pixel_assignment(&export_path, &pixel_assignment, width)
+    })
+    .await??;
+    leptos::logging::log!("Exported pixel assignment to {export_name}");
+    Ok(())
+}
+
+#[server]
+async fn import_pixel_assignment(
+    import_name: String,
+) -> Result<(Box<[Pixel]>, usize), ServerFnError> {
+    let import_path = crate::storage::model_path(ModelKind::PixelAssignment, &import_name)?;
+    let pixel_assignment = crate::storage::load_pixel_assignment(&import_path)?;
+    leptos::logging::log!("Imported pixel assignment from {import_name}");
     Ok(pixel_assignment)
 }
+
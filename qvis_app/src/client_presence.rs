@@ -0,0 +1,138 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
+
+use crate::app::{CLIENT_HEARTBEAT_INTERVAL, ClientId};
+
+/// How many heartbeats a client can miss before it counts as disconnected
+const MISSED_HEARTBEATS: u32 = 3;
+
+#[derive(Debug, Clone, Copy)]
+struct Client {
+    last_seen: Instant,
+    connected_at: Instant,
+    /// Incremented every time the client connects or reconnects
+    connections: u64,
+}
+
+impl Client {
+    fn is_connected(&self) -> bool {
+        self.last_seen.elapsed() < CLIENT_HEARTBEAT_INTERVAL * MISSED_HEARTBEATS
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Presence {
+    clients: HashMap<ClientId, Client>,
+}
+
+impl Presence {
+    /// The client that connected last among the connected ones, which is most likely the one that someone is looking at
+    fn newest_client(&self) -> Option<ClientConnection> {
+        self.clients
+            .iter()
+            .filter(|(_, client)| client.is_connected())
+            .max_by_key(|(_, client)| client.connected_at)
+            .map(|(&id, client)| ClientConnection {
+                id,
+                connections: client.connections,
+            })
+    }
+
+    /// Forgets clients that stopped sending heartbeats, which count as new clients if they come back
+    fn forget_disconnected(&mut self) {
+        self.clients.retain(|_, client| client.is_connected());
+    }
+}
+
+/// One connection of a client, which is gone once the client stops sending heartbeats or reconnects
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientConnection {
+    pub id: ClientId,
+    connections: u64,
+}
+
+/// Tracks which browsers are connected to take pictures with, from the heartbeats that they send
+#[derive(Debug, Clone)]
+pub struct ClientPresence(Arc<watch::Sender<Presence>>);
+
+impl Default for ClientPresence {
+    fn default() -> Self {
+        ClientPresence(Arc::new(watch::Sender::new(Presence::default())))
+    }
+}
+
+impl ClientPresence {
+    /// Records that the given client connected or reconnected. Anything that it was asked to do before is lost.
+    pub fn connected(&self, id: ClientId) {
+        self.0.send_modify(|presence| {
+            presence.forget_disconnected();
+            let now = Instant::now();
+            let client = presence.clients.entry(id).or_insert(Client {
+                last_seen: now,
+                connected_at: now,
+                connections: 0,
+            });
+            client.last_seen = now;
+            client.connected_at = now;
+            client.connections += 1;
+        });
+    }
+
+    /// Records that the given client is still connected
+    pub fn heartbeat(&self, id: ClientId) {
+        self.0.send_modify(|presence| {
+            presence.forget_disconnected();
+            let now = Instant::now();
+            // Clients that the server forgot, or that it never saw connect because it restarted, come back with their heartbeats
+            presence
+                .clients
+                .entry(id)
+                .and_modify(|client| client.last_seen = now)
+                .or_insert(Client {
+                    last_seen: now,
+                    connected_at: now,
+                    connections: 0,
+                });
+        });
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.0.borrow().newest_client().is_some()
+    }
+
+    /// Waits until a client is connected and returns the one that connected last, or `None` if no client connected within `timeout`
+    pub async fn wait_for_client(&self, timeout: Duration) -> Option<ClientConnection> {
+        let mut rx = self.0.subscribe();
+        let wait = async {
+            loop {
+                if let Some(client) = rx.borrow_and_update().newest_client() {
+                    return Some(client);
+                }
+                rx.changed().await.ok()?;
+            }
+        };
+        tokio::time::timeout(timeout, wait).await.ok().flatten()
+    }
+
+    /// Waits until the given connection is gone, either because its client stopped sending heartbeats or because it reconnected. Other clients coming and going don't matter.
+    pub async fn wait_for_client_loss(&self, connection: ClientConnection) {
+        let mut rx = self.0.subscribe();
+        loop {
+            {
+                let presence = rx.borrow_and_update();
+                let still_connected = presence.clients.get(&connection.id).is_some_and(|client| {
+                    client.connections == connection.connections && client.is_connected()
+                });
+                if !still_connected {
+                    return;
+                }
+            }
+            // Heartbeats going stale doesn't change anything, so check again every heartbeat
+            let _ = tokio::time::timeout(CLIENT_HEARTBEAT_INTERVAL, rx.changed()).await;
+        }
+    }
+}
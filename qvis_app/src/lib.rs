@@ -6,10 +6,20 @@
 )]
 
 pub mod app;
+#[cfg(feature = "ssr")]
+pub mod client_presence;
 pub mod log_error_panic_hook;
 pub mod messages_logger;
+pub mod pixel_assignment;
+pub mod pixel_assignment_editor;
+#[cfg(feature = "ssr")]
+pub mod registry;
+#[cfg(feature = "ssr")]
+pub mod robot_protocol;
+#[cfg(feature = "ssr")]
+pub mod server_vision;
 #[cfg(feature = "ssr")]
-pub mod pixel_assignment_ui;
+pub mod storage;
 pub mod video;
 
 #[cfg(feature = "hydrate")]
@@ -2,11 +2,10 @@ use axum::{
     Router,
     body::Body as AxumBody,
     extract::{FromRef, Path, RawQuery, State},
-    http::{HeaderMap, Request},
+    http::{HeaderMap, Request, StatusCode, header},
     response::{IntoResponse, Response as AxumResponse},
     routing::{get, post},
 };
-use bytes::Bytes;
 use leptos::prelude::*;
 use leptos_axum::{
     AxumRouteListing, LeptosRoutes, file_and_error_handler_with_context,
@@ -14,25 +13,179 @@ use leptos_axum::{
 };
 use leptos_ws::{ChannelSignal, WsSignals};
 use log::{info, warn};
-use puzzle_theory::{permutations::Permutation, puzzle_geometry::parsing::puzzle};
-use qvis::Pixel;
+use puzzle_theory::permutations::Permutation;
+use qvis::CVProcessor;
 use qvis_app::{
-    app::{App, TAKE_PICTURE_CHANNEL, TakePictureMessage, shell},
-    pixel_assignment_ui,
+    app::{
+        App, CV_PROCESSOR_CHANNEL, CVProcessorMessage, ModelKind, PictureRequest,
+        TAKE_PICTURE_CHANNEL, TakePictureMessage, shell,
+    },
+    client_presence::ClientPresence,
+    registry::Registry,
+    robot_protocol::{self, RobotCommand, RobotError, RobotErrorCode, RobotPayload},
+    server_vision::{SERVER_CAMERA_VAR, ServerCamera, ServerProcessor},
+    storage::{self, CV_PROCESSOR_FILE},
+    video::DEFAULT_STABLE_MS,
+};
+use std::{
+    str::FromStr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
 };
-use std::{sync::Mutex, thread};
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt},
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt},
     net::TcpListener,
 };
 
+/// The environment variable holding the address that robot controllers can connect to. Robot connections aren't authenticated, so set it to something like `0.0.0.0:3001` only to let robots on other machines in.
+const ROBOT_ADDR_VAR: &str = "QVIS_ROBOT_ADDR";
+/// Only robot controllers on this machine can connect unless [`ROBOT_ADDR_VAR`] says otherwise
+const DEFAULT_ROBOT_ADDR: &str = "127.0.0.1:3001";
+const ROBOT_CONNECT_TIMEOUT_VAR: &str = "QVIS_ROBOT_CONNECT_TIMEOUT_SECS";
+const ROBOT_REPLY_TIMEOUT_VAR: &str = "QVIS_ROBOT_REPLY_TIMEOUT_SECS";
+const ROBOT_RETRIES_VAR: &str = "QVIS_ROBOT_RETRIES";
+const DEFAULT_ROBOT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// Long enough for the pixel assignment that the first picture can trigger
+const DEFAULT_ROBOT_REPLY_TIMEOUT: Duration = Duration::from_secs(40 * 60);
+const DEFAULT_ROBOT_RETRIES: u32 = 2;
+
+/// How long robot commands wait on the browser
+#[derive(Debug, Clone, Copy)]
+struct RobotTimeouts {
+    /// How long to wait for a browser to connect before giving up on a command
+    connect: Duration,
+    /// How long to wait for the browser to reply to a command, across every retry
+    reply: Duration,
+    /// How many times to resend a command after the browser disconnects or reloads
+    retries: u32,
+}
+
+impl RobotTimeouts {
+    fn from_env() -> RobotTimeouts {
+        RobotTimeouts {
+            connect: env_or(
+                ROBOT_CONNECT_TIMEOUT_VAR,
+                DEFAULT_ROBOT_CONNECT_TIMEOUT.as_secs(),
+            )
+            .map_or(DEFAULT_ROBOT_CONNECT_TIMEOUT, Duration::from_secs),
+            reply: env_or(
+                ROBOT_REPLY_TIMEOUT_VAR,
+                DEFAULT_ROBOT_REPLY_TIMEOUT.as_secs(),
+            )
+            .map_or(DEFAULT_ROBOT_REPLY_TIMEOUT, Duration::from_secs),
+            retries: env_or(ROBOT_RETRIES_VAR, DEFAULT_ROBOT_RETRIES)
+                .unwrap_or(DEFAULT_ROBOT_RETRIES),
+        }
+    }
+}
+
+const CHECKPOINT_EVERY_VAR: &str = "QVIS_CHECKPOINT_EVERY";
+const CHECKPOINT_INTERVAL_VAR: &str = "QVIS_CHECKPOINT_INTERVAL_SECS";
+const CHECKPOINTS_KEPT_VAR: &str = "QVIS_CHECKPOINTS_KEPT";
+const DEFAULT_CHECKPOINT_EVERY: usize = 20;
+const DEFAULT_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const DEFAULT_CHECKPOINTS_KEPT: usize = 10;
+
+/// When the server checkpoints its processor
+#[derive(Debug, Clone, Copy)]
+struct CheckpointSettings {
+    /// How many calibrations to checkpoint after
+    every: usize,
+    /// How often to checkpoint a processor that changed since the last checkpoint
+    interval: Duration,
+    /// How many checkpoints to keep before deleting the oldest
+    kept: usize,
+}
+
+impl CheckpointSettings {
+    fn from_env() -> CheckpointSettings {
+        CheckpointSettings {
+            every: env_or(CHECKPOINT_EVERY_VAR, DEFAULT_CHECKPOINT_EVERY)
+                .unwrap_or(DEFAULT_CHECKPOINT_EVERY)
+                .max(1),
+            interval: env_or(
+                CHECKPOINT_INTERVAL_VAR,
+                DEFAULT_CHECKPOINT_INTERVAL.as_secs(),
+            )
+            .filter(|&secs| secs > 0)
+            .map_or(DEFAULT_CHECKPOINT_INTERVAL, Duration::from_secs),
+            kept: env_or(CHECKPOINTS_KEPT_VAR, DEFAULT_CHECKPOINTS_KEPT)
+                .unwrap_or(DEFAULT_CHECKPOINTS_KEPT),
+        }
+    }
+}
+
+/// Reads the environment variable `name`, falling back to `default` if it isn't set. Returns `None` if it is set but invalid.
+fn env_or<T: FromStr>(name: &str, default: T) -> Option<T> {
+    match std::env::var(name) {
+        Ok(value) => {
+            let parsed = value.trim().parse().ok();
+            if parsed.is_none() {
+                warn!("Ignoring invalid value {value:?} of {name}");
+            }
+            parsed
+        }
+        Err(_) => Some(default),
+    }
+}
+
+/// Everything that robot commands need, shared between every robot controller
+#[derive(Clone)]
+struct RobotContext {
+    server_signals: WsSignals,
+    client_presence: ClientPresence,
+    server_processor: ServerProcessor,
+    registry: Registry,
+    /// Takes the pictures instead of the browser if there is one
+    server_camera: Option<Arc<ServerCamera>>,
+    timeouts: RobotTimeouts,
+    /// Only one robot command can be in flight at a time because they all share the same channel
+    lock: Arc<tokio::sync::Mutex<()>>,
+    /// The id of the next request for a picture, which only ever grows
+    next_request: Arc<AtomicU64>,
+}
+
 #[derive(Clone, FromRef)]
 pub struct AppState {
     server_signals: WsSignals,
     routes: Option<Vec<AxumRouteListing>>,
     options: LeptosOptions,
-    pixel_assignment_ui_tx:
-        std::sync::mpsc::Sender<(tokio::sync::oneshot::Sender<Box<[Pixel]>>, Bytes)>,
+    client_presence: ClientPresence,
+    server_processor: ServerProcessor,
+    registry: Registry,
+}
+
+/// Serves a processor from the model directory as a download
+async fn download_cv_processor(Path(name): Path<String>) -> AxumResponse {
+    let path = match storage::model_path(ModelKind::CVProcessor, &name) {
+        Ok(path) => path.with_extension("json"),
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    match tokio::fs::read(&path).await {
+        Ok(json) => (
+            [
+                (header::CONTENT_TYPE, "application/json".to_owned()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{name}.json\""),
+                ),
+            ],
+            json,
+        )
+            .into_response(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => (
+            StatusCode::NOT_FOUND,
+            format!("No CVProcessor is saved as {name}"),
+        )
+            .into_response(),
+        Err(e) => {
+            warn!("Failed to read {}: {e}", path.display());
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
 }
 
 async fn server_fn_handler(
@@ -46,7 +199,9 @@ async fn server_fn_handler(
         move || {
             provide_context(state.options.clone());
             provide_context(state.server_signals.clone());
-            provide_context(state.pixel_assignment_ui_tx.clone());
+            provide_context(state.client_presence.clone());
+            provide_context(state.server_processor.clone());
+            provide_context(state.registry.clone());
         },
         request,
     )
@@ -68,17 +223,12 @@ async fn leptos_routes_handler(state: State<AppState>, req: Request<AxumBody>) -
 }
 
 #[tokio::main]
-async fn server_main(
-    pixel_assignment_ui_tx: std::sync::mpsc::Sender<(
-        tokio::sync::oneshot::Sender<Box<[Pixel]>>,
-        Bytes,
-    )>,
-) {
+async fn main() {
     let conf = get_configuration(None).unwrap();
     let leptos_options = conf.leptos_options;
     let addr = leptos_options.site_addr;
 
-    let mut server_signals = WsSignals::new();
+    let server_signals = WsSignals::new();
     let server_signals2 = server_signals.clone();
     let server_signals3 = server_signals.clone();
     let (routes, _) = generate_route_list_with_exclusions_and_ssg_and_context(
@@ -86,11 +236,28 @@ async fn server_main(
         None,
         move || provide_context(server_signals2.clone()),
     );
+    let client_presence = ClientPresence::default();
+    let server_processor = ServerProcessor::default();
+    if let Some(cv_processor) = load_cv_processor() {
+        server_processor.set(cv_processor);
+    }
+    let registry = Registry::load();
+    tokio::spawn(sync_cv_processor(
+        server_processor.clone(),
+        server_signals.clone(),
+    ));
+    tokio::spawn(checkpoint_cv_processor(
+        server_processor.clone(),
+        registry.clone(),
+        CheckpointSettings::from_env(),
+    ));
     let state = AppState {
         options: leptos_options.clone(),
         routes: Some(routes.clone()),
         server_signals: server_signals.clone(),
-        pixel_assignment_ui_tx,
+        client_presence: client_presence.clone(),
+        server_processor: server_processor.clone(),
+        registry: registry.clone(),
     };
 
     let app = Router::new()
@@ -98,6 +265,7 @@ async fn server_main(
             "/api/{*fn_name}",
             post(server_fn_handler).get(server_fn_handler),
         )
+        .route("/models/cv_processors/{name}", get(download_cv_processor))
         .leptos_routes_with_handler(routes, get(leptos_routes_handler))
         .fallback(file_and_error_handler_with_context::<AppState, _>(
             move || provide_context(server_signals3.clone()),
@@ -105,8 +273,27 @@ async fn server_main(
         ))
         .with_state(state);
 
+    let robot = RobotContext {
+        server_signals,
+        client_presence,
+        server_processor,
+        registry,
+        server_camera: open_server_camera().map(Arc::new),
+        timeouts: RobotTimeouts::from_env(),
+        lock: Arc::new(tokio::sync::Mutex::new(())),
+        next_request: Arc::new(AtomicU64::new(1)),
+    };
+    {
+        let mut robot = robot.clone();
+        tokio::spawn(async move {
+            let stdin = tokio::io::BufReader::new(tokio::io::stdin());
+            robot_tui(stdin, tokio::io::stdout(), &mut robot).await;
+        });
+    }
     tokio::spawn(async move {
-        robot_tui(&mut server_signals).await;
+        let robot_addr =
+            std::env::var(ROBOT_ADDR_VAR).unwrap_or_else(|_| DEFAULT_ROBOT_ADDR.to_owned());
+        robot_listener(&robot_addr, &robot).await;
     });
 
     info!("listening on {addr}");
@@ -116,99 +303,423 @@ async fn server_main(
         .unwrap();
 }
 
-async fn robot_tui(server_signals: &mut WsSignals) {
-    let mut stdin = tokio::io::BufReader::new(tokio::io::stdin()).lines();
-    let mut stdout = tokio::io::stdout();
-    while let Ok(Some(line)) = stdin.next_line().await {
-        if line.starts_with("TAKE_PICTURE") {
-            let done_string = take_picture(server_signals, None)
-                .await
-                .map(|v| {
-                    let (p, c) = v.unwrap();
-                    format!("{p};{:.2}", c * 100.)
+/// Loads the processor that the server saved before it was restarted, if any
+fn load_cv_processor() -> Option<CVProcessor> {
+    let path = storage::data_dir().join(CV_PROCESSOR_FILE);
+    let file = match std::fs::File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!("Failed to open {}: {e}", path.display());
+            return None;
+        }
+    };
+    match leptos::serde_json::from_reader(std::io::BufReader::new(file)) {
+        Ok(cv_processor) => {
+            info!("Loaded CVProcessor from {}", path.display());
+            Some(cv_processor)
+        }
+        Err(e) => {
+            warn!("Failed to load CVProcessor from {}: {e}", path.display());
+            None
+        }
+    }
+}
+
+/// Pushes the server's processor to every client every time it changes, so that it survives reloads
+async fn sync_cv_processor(server_processor: ServerProcessor, mut server_signals: WsSignals) {
+    let channel = ChannelSignal::new_with_context(&mut server_signals, CV_PROCESSOR_CHANNEL)
+        .expect("Failed to create the CVProcessor channel");
+    let mut rx = server_processor.0.subscribe();
+    while rx.changed().await.is_ok() {
+        let Some(cv_processor) = rx.borrow_and_update().clone() else {
+            continue;
+        };
+        if let Err(e) = channel.send_message(CVProcessorMessage(cv_processor)) {
+            warn!("Failed to push CVProcessor to clients: {e}");
+        }
+    }
+}
+
+/// Checkpoints the server's processor every [`CheckpointSettings::every`] calibrations and every [`CheckpointSettings::interval`] that it changed in, so that a crash loses little calibration and a bad calibration can be undone. Every checkpoint also saves it as the current processor and to the active profile, so that it survives restarts.
+async fn checkpoint_cv_processor(
+    server_processor: ServerProcessor,
+    registry: Registry,
+    settings: CheckpointSettings,
+) {
+    let mut rx = server_processor.0.subscribe();
+    let mut timer = tokio::time::interval(settings.interval);
+    timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // The first tick is immediate
+    timer.tick().await;
+
+    // How many calibrations the processor had at the last checkpoint
+    let mut checkpointed = rx.borrow().as_ref().map(CVProcessor::calibrations);
+    let mut changed_since_checkpoint = false;
+    loop {
+        let timed_out = tokio::select! {
+            changed = rx.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                changed_since_checkpoint = true;
+                false
+            }
+            _ = timer.tick() => true,
+        };
+        let Some(cv_processor) = rx.borrow_and_update().clone() else {
+            continue;
+        };
+
+        let calibrations = cv_processor.calibrations();
+        let due = match checkpointed {
+            // Fewer calibrations means that a different processor was installed, which is worth keeping
+            Some(checkpointed) => {
+                calibrations >= checkpointed + settings.every || calibrations < checkpointed
+            }
+            None => true,
+        };
+        if !(due || (timed_out && changed_since_checkpoint)) {
+            continue;
+        }
+
+        let registry = registry.clone();
+        match tokio::task::spawn_blocking(move || {
+            let json = leptos::serde_json::to_vec(&cv_processor)?;
+            let path = storage::data_dir().join(CV_PROCESSOR_FILE);
+            if let Err(e) = storage::write_atomically(&path, &json) {
+                warn!("Failed to save CVProcessor: {e}");
+            }
+            if let Err(e) = registry.save_active(&cv_processor) {
+                warn!("Failed to save the active profile: {e}");
+            }
+            storage::save_checkpoint(&cv_processor, settings.kept)
+        })
+        .await
+        {
+            Ok(Ok(checkpoint)) => {
+                info!("Saved checkpoint {}", checkpoint.name);
+                checkpointed = Some(calibrations);
+                changed_since_checkpoint = false;
+            }
+            Ok(Err(e)) => warn!("Failed to save checkpoint: {e}"),
+            Err(e) => warn!("Failed to save checkpoint: {e}"),
+        }
+    }
+}
+
+/// Opens the camera given by [`SERVER_CAMERA_VAR`], if any
+fn open_server_camera() -> Option<ServerCamera> {
+    let index = std::env::var(SERVER_CAMERA_VAR).ok()?;
+    let Ok(index) = index.trim().parse() else {
+        warn!("Ignoring invalid value {index:?} of {SERVER_CAMERA_VAR}");
+        return None;
+    };
+    match ServerCamera::open(index) {
+        Ok(camera) => {
+            info!("Taking robot pictures with server camera {index}");
+            Some(camera)
+        }
+        Err(e) => {
+            warn!(
+                "Failed to open server camera {index}, taking robot pictures in the browser instead: {e}"
+            );
+            None
+        }
+    }
+}
+
+/// Accepts robot controllers over TCP. Every connection speaks the same protocol as stdin, see [`robot_tui`].
+async fn robot_listener(addr: &str, robot: &RobotContext) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            warn!("Failed to listen for robot controllers on {addr}: {e}");
+            return;
+        }
+    };
+    info!("listening for robot controllers on {addr}");
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("Failed to accept robot controller: {e}");
+                continue;
+            }
+        };
+        info!("Robot controller connected from {peer}");
+        let mut robot = robot.clone();
+        tokio::spawn(async move {
+            let (reader, writer) = stream.into_split();
+            let reader = tokio::io::BufReader::new(reader);
+            robot_tui(reader, writer, &mut robot).await;
+            info!("Robot controller {peer} disconnected");
+        });
+    }
+}
+
+/// Reads robot commands line by line and answers each of them, until `reader` ends or `writer` fails. See [`robot_protocol`] for the commands and answers.
+async fn robot_tui(
+    reader: impl AsyncBufRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+    robot: &mut RobotContext,
+) {
+    let mut lines = reader.lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let Some((dialect, command)) = robot_protocol::parse_line(&line) else {
+            continue;
+        };
+        let outcome = match command {
+            Ok(command) => {
+                let lock = Arc::clone(&robot.lock);
+                let _guard = lock.lock().await;
+                run_robot_command(robot, command).await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = &outcome {
+            warn!("Robot command failed: {e:?}");
+        }
+
+        let reply = robot_protocol::format_reply(dialect, &outcome);
+        let written = async {
+            writer.write_all(reply.as_bytes()).await?;
+            writer.flush().await
+        };
+        if let Err(e) = written.await {
+            warn!("Failed to reply to robot controller: {e}");
+            return;
+        }
+    }
+}
+
+async fn run_robot_command(
+    robot: &mut RobotContext,
+    command: RobotCommand,
+) -> Result<RobotPayload, RobotError> {
+    let calibration_permutation = match command {
+        RobotCommand::SelectProfile(name) => return select_profile(robot, name).await,
+        RobotCommand::TakePicture => None,
+        RobotCommand::Calibrate(permutation) => Some(permutation),
+    };
+    if let Some(server_camera) = &robot.server_camera {
+        return run_robot_command_on_server(
+            robot.server_processor.clone(),
+            Arc::clone(server_camera),
+            calibration_permutation,
+        )
+        .await;
+    }
+
+    match calibration_permutation {
+        None => match take_picture(robot, None).await? {
+            TakePictureMessage::PermutationResult(_, permutation, confidence, waited, occluded) => {
+                Ok(RobotPayload::Recognized {
+                    permutation,
+                    confidence,
+                    waited_ms: millis(waited),
+                    occluded,
                 })
-                .unwrap_or_else(|e| e.to_string());
-            stdout
-                .write_all(format!("DONE {done_string}\n").as_bytes())
-                .await
-                .unwrap();
-        } else if line.starts_with("CALIBRATE") {
-            let perm_str = line.trim_start_matches("CALIBRATE").trim();
-            let done_string = if let Ok(permutation) = perm_str.parse::<Permutation>() {
-                take_picture(server_signals, Some(permutation))
-                    .await
-                    .map(|n| {
-                        assert!(n.is_none());
-                        String::new()
-                    })
-                    .unwrap_or_else(|e| e.to_string())
-            } else {
-                format!("Invalid permutation string: {perm_str}")
-            };
+            }
+            TakePictureMessage::Failed(_, message) => {
+                Err(RobotError::new(RobotErrorCode::CaptureFailed, message))
+            }
+            reply => Err(RobotError::new(
+                RobotErrorCode::UnexpectedReply,
+                format!("Expected a permutation but the client replied with {reply:?}"),
+            )),
+        },
+        Some(permutation) => match take_picture(robot, Some(permutation)).await? {
+            TakePictureMessage::Calibrated(_, waited) => Ok(RobotPayload::Calibrated {
+                waited_ms: millis(waited),
+            }),
+            TakePictureMessage::Failed(_, message) => {
+                Err(RobotError::new(RobotErrorCode::CaptureFailed, message))
+            }
+            reply => Err(RobotError::new(
+                RobotErrorCode::UnexpectedReply,
+                format!("Expected a calibration but the client replied with {reply:?}"),
+            )),
+        },
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
 
-            stdout
-                .write_all(format!("DONE {done_string}\n").as_bytes())
-                .await
-                .unwrap();
-        } else {
-            leptos::logging::log!("WARNING: Unknown command: {}", line);
+/// Makes the given profile the active one and replaces the server's processor with it, which the server then pushes to every client
+async fn select_profile(robot: &RobotContext, name: String) -> Result<RobotPayload, RobotError> {
+    let server_error =
+        |e: &dyn std::fmt::Display| RobotError::new(RobotErrorCode::ServerError, e.to_string());
+    let registry = robot.registry.clone();
+    let selected = {
+        let name = name.clone();
+        tokio::task::spawn_blocking(move || registry.select(&name))
+            .await
+            .map_err(|e| server_error(&e))?
+    };
+    match selected {
+        Ok(cv_processor) => {
+            robot.server_processor.set(cv_processor);
+            info!("Selected profile {name}");
+            Ok(RobotPayload::ProfileSelected { name })
         }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(RobotError::new(
+            RobotErrorCode::UnknownProfile,
+            e.to_string(),
+        )),
+        Err(e) => Err(server_error(&e)),
     }
 }
 
+/// Takes a picture with the server camera once the puzzle holds still and recognizes or calibrates with it using the server's processor, so that no browser is needed
+async fn run_robot_command_on_server(
+    server_processor: ServerProcessor,
+    server_camera: Arc<ServerCamera>,
+    calibration_permutation: Option<Permutation>,
+) -> Result<RobotPayload, RobotError> {
+    let no_processor = || {
+        RobotError::new(
+            RobotErrorCode::NoProcessor,
+            "The server has no CVProcessor yet; assign pixels in the browser first",
+        )
+    };
+    tokio::task::spawn_blocking(move || {
+        let capture_failed =
+            |e: opencv::Error| RobotError::new(RobotErrorCode::CaptureFailed, e.message);
+        let (picture, waited) = server_camera
+            .capture_when_stable(
+                &server_processor,
+                Duration::from_millis(DEFAULT_STABLE_MS.into()),
+            )
+            .map_err(capture_failed)?;
+        match calibration_permutation {
+            None => {
+                let (permutation, confidence, occluded) = server_processor
+                    .process(&picture)
+                    .map_err(capture_failed)?
+                    .ok_or_else(no_processor)?;
+                info!(
+                    "Processed {permutation} with confidence {:.2}",
+                    confidence * 100.
+                );
+                Ok(RobotPayload::Recognized {
+                    permutation,
+                    confidence,
+                    waited_ms: millis(waited),
+                    occluded,
+                })
+            }
+            Some(permutation) => {
+                if server_processor
+                    .calibrate(&picture, &permutation)
+                    .map_err(capture_failed)?
+                {
+                    Ok(RobotPayload::Calibrated {
+                        waited_ms: millis(waited),
+                    })
+                } else {
+                    Err(no_processor())
+                }
+            }
+        }
+    })
+    .await
+    .map_err(|e| RobotError::new(RobotErrorCode::ServerError, e.to_string()))?
+}
+
+/// Asks the browser that connected last to take a picture and either recognize or calibrate with it, and returns its reply. Waits for a browser to connect if there isn't one, and asks again if that browser disconnects or reloads before replying, while other browsers coming and going don't matter.
 async fn take_picture(
-    server_signals: &mut WsSignals,
+    robot: &mut RobotContext,
     calibration_permutation: Option<Permutation>,
-) -> Result<Option<(Permutation, f64)>, ServerFnError> {
-    let channel = ChannelSignal::new_with_context(server_signals, TAKE_PICTURE_CHANNEL)
-        .map_err(ServerFnError::new)?;
+) -> Result<TakePictureMessage, RobotError> {
+    let server_error =
+        |e: &dyn std::fmt::Display| RobotError::new(RobotErrorCode::ServerError, e.to_string());
+    let RobotTimeouts {
+        connect,
+        reply,
+        retries,
+    } = robot.timeouts;
 
-    let (response_tx, response_rx) = tokio::sync::oneshot::channel();
+    let channel = ChannelSignal::new_with_context(&mut robot.server_signals, TAKE_PICTURE_CHANNEL)
+        .map_err(|e| server_error(&e))?;
+
+    // Every attempt asks with the same id, so that only one answer counts
+    let request = robot.next_request.fetch_add(1, Ordering::Relaxed);
+    let (response_tx, mut response_rx) = tokio::sync::oneshot::channel();
     let response_tx = Mutex::new(Some(response_tx));
 
     channel
         .on_server(move |message: &TakePictureMessage| {
             info!("Received message {message:#?}");
-            if let Some(response_tx) = response_tx.lock().unwrap().take() {
-                match message {
-                    TakePictureMessage::PermutationResult(permutation, confidence) => {
-                        response_tx.send(Some((permutation.clone(), *confidence))).unwrap();
-                    }
-                    TakePictureMessage::Calibrated => {
-                        response_tx.send(None).unwrap();
-                    }
-                    m @ (TakePictureMessage::TakePicture | TakePictureMessage::Calibrate(_)) => {
-                        warn!("Received {m:?} on server, which should not happen");
+            match message {
+                // An answer to another robot command, which handles it itself
+                TakePictureMessage::PermutationResult(id, ..)
+                | TakePictureMessage::Calibrated(id, _)
+                | TakePictureMessage::Failed(id, _)
+                    if *id != request => {}
+                TakePictureMessage::PermutationResult(..)
+                | TakePictureMessage::Calibrated(..)
+                | TakePictureMessage::Failed(..) => {
+                    if let Some(response_tx) = response_tx.lock().unwrap().take() {
+                        // The robot command may have been given up on, in which case nobody needs the reply
+                        let _ = response_tx.send(message.clone());
+                    } else {
+                        warn!(
+                            "Received message {message:#?} but response channel was already used"
+                        );
                     }
                 }
-            } else {
-                warn!("Received message {message:#?} but response channel was already used. This task will likely hang now.");
+                m @ (TakePictureMessage::TakePicture(_) | TakePictureMessage::Calibrate(..)) => {
+                    warn!("Received {m:?} on server, which should not happen");
+                }
             }
         })
-        .map_err(ServerFnError::new)?;
+        .map_err(|e| server_error(&e))?;
 
-    let message = if let Some(calibration_permutation) = calibration_permutation {
-        TakePictureMessage::Calibrate(calibration_permutation)
-    } else {
-        TakePictureMessage::TakePicture
-    };
-
-    channel.send_message(message).map_err(ServerFnError::new)?;
-
-    response_rx.await.map_err(ServerFnError::new)
-}
-
-fn main() {
-    let (pixel_assignment_ui_tx, pixel_assignment_ui_rx) =
-        std::sync::mpsc::channel::<(tokio::sync::oneshot::Sender<Box<[Pixel]>>, Bytes)>();
-
-    thread::spawn(move || server_main(pixel_assignment_ui_tx));
+    let deadline = tokio::time::Instant::now() + reply;
+    for attempt in 1..=retries + 1 {
+        let Some(client) = robot.client_presence.wait_for_client(connect).await else {
+            return Err(RobotError::new(
+                RobotErrorCode::NoClient,
+                format!(
+                    "No client connected within {}s; open the app in a browser",
+                    connect.as_secs()
+                ),
+            ));
+        };
+        // Only one client takes the picture, so that it is taken once
+        let picture_request = PictureRequest {
+            client: client.id,
+            id: request,
+        };
+        let message = match &calibration_permutation {
+            Some(permutation) => {
+                TakePictureMessage::Calibrate(picture_request, permutation.clone())
+            }
+            None => TakePictureMessage::TakePicture(picture_request),
+        };
+        channel
+            .send_message(message.clone())
+            .map_err(|e| server_error(&e))?;
 
-    // For some reason highgui doesn't work unless it's on the main thread
-    let puzzle_geometry = puzzle("3x3");
-    while let Ok((pixel_assignment_done_tx, image)) = pixel_assignment_ui_rx.recv() {
-        let assignment =
-            pixel_assignment_ui::pixel_assignment_ui(&puzzle_geometry, &image).unwrap();
-        pixel_assignment_done_tx.send(assignment).unwrap();
+        tokio::select! {
+            response = &mut response_rx => return response.map_err(|e| server_error(&e)),
+            () = robot.client_presence.wait_for_client_loss(client) => {
+                warn!("Lost the client before it replied to {message:?} (attempt {attempt} of {})", retries + 1);
+            }
+            () = tokio::time::sleep_until(deadline) => {
+                return Err(RobotError::new(
+                    RobotErrorCode::TimedOut,
+                    format!("The client didn't reply within {}s", reply.as_secs()),
+                ));
+            }
+        }
     }
+
+    Err(RobotError::new(
+        RobotErrorCode::ClientLost,
+        format!("Lost the client before it replied, {} times", retries + 1),
+    ))
 }
@@ -0,0 +1,706 @@
+use internment::ArcIntern;
+use puzzle_theory::puzzle_geometry::PuzzleGeometry;
+use qvis::Pixel;
+use rand::{Rng, seq::SliceRandom};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+pub const EROSION_SIZE_MINDEFMAX: [i32; 3] = [2, 4, 20];
+pub const UPPER_DIFF_MINDEFMAX: [i32; 3] = [0, 2, 5];
+pub const MAX_PIXEL_VALUE: i32 = 255;
+pub const ERODE_UNTIL_PERCENT: (i32, i32) = (1, 3);
+pub const MIN_SAMPLES: i32 = 30;
+pub const NUM_QVIS_PIXELS: usize = 20;
+/// The range of the number of pixels that can be sampled per sticker
+pub const NUM_SAMPLES_MINDEFMAX: [i32; 3] = [1, 20, 200];
+/// The range of the percentage of a selection that erosion stops at
+pub const ERODE_UNTIL_PERCENT_MINDEFMAX: [i32; 3] = [1, 33, 100];
+/// The range of the number of pixels that erosion stops at
+pub const MIN_SAMPLES_MINDEFMAX: [i32; 3] = [1, 30, 500];
+
+/// The diameter of the brush and eraser, in image pixels
+pub const BRUSH_SIZE_MINDEFMAX: [i32; 3] = [1, 8, 40];
+
+/// How the pixels of the current sticker are selected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SelectionTool {
+    /// Flood fill from where the drag starts, with a tolerance set by the length and direction of the drag
+    #[default]
+    FloodFill,
+    /// Click each vertex of a polygon, then click the first vertex again to fill it
+    Polygon,
+    /// Drag out a rectangle to fill
+    Rectangle,
+    /// Paint pixels into the selection
+    Brush,
+    /// Paint pixels out of the selection, including ones selected by flood fill
+    Eraser,
+}
+
+impl SelectionTool {
+    pub const ALL: [SelectionTool; 5] = [
+        SelectionTool::FloodFill,
+        SelectionTool::Polygon,
+        SelectionTool::Rectangle,
+        SelectionTool::Brush,
+        SelectionTool::Eraser,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SelectionTool::FloodFill => "Flood fill",
+            SelectionTool::Polygon => "Polygon",
+            SelectionTool::Rectangle => "Rectangle",
+            SelectionTool::Brush => "Brush",
+            SelectionTool::Eraser => "Eraser",
+        }
+    }
+
+    /// The position of the tool in [`SelectionTool::ALL`]
+    pub fn index(self) -> usize {
+        SelectionTool::ALL
+            .iter()
+            .position(|&tool| tool == self)
+            .unwrap()
+    }
+
+    /// The tool after this one in [`SelectionTool::ALL`], wrapping around
+    pub fn next(self) -> SelectionTool {
+        SelectionTool::ALL[(self.index() + 1) % SelectionTool::ALL.len()]
+    }
+
+    /// Whether the flood fill is part of the selection, given whether the user asked to combine it with the manual tools
+    pub fn uses_flood_fill(self, combine_flood_fill: bool) -> bool {
+        self == SelectionTool::FloodFill || combine_flood_fill
+    }
+
+    /// Whether the pixels selected with the polygon, rectangle, brush and eraser tools are part of the selection
+    pub fn uses_manual_selection(self, combine_flood_fill: bool) -> bool {
+        self != SelectionTool::FloodFill || combine_flood_fill
+    }
+}
+
+/// How the pixels that get assigned to a sticker are picked from the pixels left after erosion
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SamplingStrategy {
+    /// Pick pixels uniformly at random
+    #[default]
+    Random,
+    /// Split the selection into a grid with about one cell per sample and pick a random pixel from each cell
+    Stratified,
+    /// Start from a random pixel and repeatedly pick the pixel farthest from every pixel picked so far
+    FarthestPoint,
+}
+
+impl SamplingStrategy {
+    pub const ALL: [SamplingStrategy; 3] = [
+        SamplingStrategy::Random,
+        SamplingStrategy::Stratified,
+        SamplingStrategy::FarthestPoint,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SamplingStrategy::Random => "Random",
+            SamplingStrategy::Stratified => "Stratified",
+            SamplingStrategy::FarthestPoint => "Farthest point",
+        }
+    }
+
+    /// The position of the strategy in [`SamplingStrategy::ALL`]
+    pub fn index(self) -> usize {
+        SamplingStrategy::ALL
+            .iter()
+            .position(|&strategy| strategy == self)
+            .unwrap()
+    }
+}
+
+/// How many pixels are assigned to each sticker and how they are chosen. This lasts for one pixel assignment session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SamplingConfig {
+    /// The most pixels that are assigned to each sticker
+    pub num_samples: usize,
+    pub strategy: SamplingStrategy,
+    /// Erosion stops once the selection has shrunk to this fraction (numerator, denominator) of its size
+    pub erode_until: (i32, i32),
+    /// Erosion stops once the selection has shrunk to this many pixels
+    pub min_samples: i32,
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        SamplingConfig {
+            num_samples: NUM_QVIS_PIXELS,
+            strategy: SamplingStrategy::default(),
+            erode_until: ERODE_UNTIL_PERCENT,
+            min_samples: MIN_SAMPLES,
+        }
+    }
+}
+
+impl SamplingConfig {
+    /// The erosion target as a whole percentage, for showing on a slider
+    pub fn erode_until_percent(&self) -> i32 {
+        self.erode_until.0 * 100 / self.erode_until.1
+    }
+
+    /// Sets the erosion target to a whole percentage, leaving it alone if it already rounds to that percentage
+    pub fn set_erode_until_percent(&mut self, percent: i32) {
+        if percent != self.erode_until_percent() {
+            self.erode_until = (percent, 100);
+        }
+    }
+
+    /// Whether a selection that started with `original` pixels has been eroded enough once it has `current` pixels
+    pub fn has_eroded_enough(&self, original: usize, current: usize) -> bool {
+        let (numerator, denominator) = self.erode_until;
+        #[allow(clippy::cast_sign_loss)]
+        let (numerator, denominator, min_samples) = (
+            numerator.max(0) as usize,
+            denominator.max(1) as usize,
+            self.min_samples.max(0) as usize,
+        );
+        current <= original * numerator / denominator || current <= min_samples
+    }
+
+    /// Picks up to `num_samples` of `candidates`, which are indices into an image `width` pixels wide. `candidates` is reordered.
+    pub fn sample(&self, candidates: &mut [usize], width: usize, rng: &mut impl Rng) -> Vec<usize> {
+        let num_samples = self.num_samples.min(candidates.len());
+        match self.strategy {
+            SamplingStrategy::Random => candidates.partial_shuffle(rng, num_samples).0.to_vec(),
+            SamplingStrategy::Stratified => stratified_sample(candidates, width, num_samples, rng),
+            SamplingStrategy::FarthestPoint => {
+                farthest_point_sample(candidates, width, num_samples, rng)
+            }
+        }
+    }
+}
+
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+fn stratified_sample(
+    candidates: &mut [usize],
+    width: usize,
+    num_samples: usize,
+    rng: &mut impl Rng,
+) -> Vec<usize> {
+    if num_samples == 0 {
+        return Vec::new();
+    }
+    let xy = |i: usize| (i % width, i / width);
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (usize::MAX, usize::MAX, 0, 0);
+    for &i in candidates.iter() {
+        let (x, y) = xy(i);
+        (min_x, min_y) = (min_x.min(x), min_y.min(y));
+        (max_x, max_y) = (max_x.max(x), max_y.max(y));
+    }
+    let (box_width, box_height) = (max_x - min_x + 1, max_y - min_y + 1);
+
+    // Choose a grid with about `num_samples` cells that are as square as possible
+    let cols = ((num_samples as f64 * box_width as f64 / box_height as f64)
+        .sqrt()
+        .round() as usize)
+        .clamp(1, num_samples);
+    let rows = num_samples.div_ceil(cols);
+
+    // Visiting the candidates in a random order and keeping the first one seen in each cell picks a random pixel from each cell
+    candidates.shuffle(rng);
+    let mut cells: HashMap<(usize, usize), usize> = HashMap::new();
+    for &i in candidates.iter() {
+        let (x, y) = xy(i);
+        let cell = (
+            (x - min_x) * cols / box_width,
+            (y - min_y) * rows / box_height,
+        );
+        cells.entry(cell).or_insert(i);
+    }
+    let mut ret: Vec<usize> = cells.into_values().collect();
+    ret.sort_unstable();
+    ret.shuffle(rng);
+    ret.truncate(num_samples);
+
+    // Cells without any selected pixels leave gaps, which are filled with random pixels
+    if ret.len() < num_samples {
+        let picked: HashSet<usize> = ret.iter().copied().collect();
+        ret.extend(
+            candidates
+                .iter()
+                .copied()
+                .filter(|i| !picked.contains(i))
+                .take(num_samples - ret.len()),
+        );
+    }
+    ret
+}
+
+fn farthest_point_sample(
+    candidates: &[usize],
+    width: usize,
+    num_samples: usize,
+    rng: &mut impl Rng,
+) -> Vec<usize> {
+    if num_samples == 0 {
+        return Vec::new();
+    }
+    let xy = |i: usize| (i % width, i / width);
+    let distance_squared = |a: usize, b: usize| {
+        let ((ax, ay), (bx, by)) = (xy(a), xy(b));
+        ax.abs_diff(bx).pow(2) + ay.abs_diff(by).pow(2)
+    };
+
+    let first = candidates[rng.random_range(0..candidates.len())];
+    let mut ret = vec![first];
+    let mut nearest: Vec<usize> = candidates
+        .iter()
+        .map(|&i| distance_squared(i, first))
+        .collect();
+    while ret.len() < num_samples {
+        let (farthest_idx, _) = nearest
+            .iter()
+            .enumerate()
+            .max_by_key(|&(_, distance)| *distance)
+            .unwrap();
+        let farthest = candidates[farthest_idx];
+        ret.push(farthest);
+        for (&i, distance) in candidates.iter().zip(&mut nearest) {
+            *distance = (*distance).min(distance_squared(i, farthest));
+        }
+    }
+    ret
+}
+
+/// The number of pixels that [`qvis::CVProcessor::process_image`] looks at per image and the number of color densities it computes for them, if `num_samples` pixels are assigned to every sticker in `targets`
+pub fn projected_inference_cost(
+    targets: &[AssignmentTarget],
+    num_samples: usize,
+) -> (usize, usize) {
+    let num_stickers = targets
+        .iter()
+        .filter(|target| matches!(target.pixel, Pixel::Sticker(_)))
+        .count();
+    let num_colors = targets.len() - num_stickers;
+    let pixels = num_stickers * num_samples;
+    (pixels, pixels * num_colors)
+}
+
+/// How much inference will cost with the given sampling, for showing to the user
+pub fn inference_cost_text(targets: &[AssignmentTarget], config: &SamplingConfig) -> String {
+    let (pixels, densities) = projected_inference_cost(targets, config.num_samples);
+    format!("Inference: {pixels} pixels, {densities} color densities per image")
+}
+
+/// The weight of the pixels right at the border of a sticker, relative to the pixels furthest from it
+pub const MIN_BORDER_WEIGHT: f64 = 0.05;
+
+/// How much a sampled pixel should count during inference, given its distance to the border of its selection and the largest such distance in the selection. Pixels near the border are more likely to catch a neighbouring sticker or the plastic, so they count less.
+pub fn border_weight(distance: f64, max_distance: f64) -> f64 {
+    if max_distance > 0.0 {
+        (distance / max_distance).clamp(MIN_BORDER_WEIGHT, 1.0)
+    } else {
+        1.0
+    }
+}
+
+/// How much one step of the mouse wheel zooms the assignment view by
+pub const ZOOM_STEP: f64 = 1.25;
+/// The smallest width or height, in image pixels, that the assignment view can be zoomed into
+pub const MIN_VIEW_SIZE: usize = 8;
+
+/// The part of an image that is being shown, in image pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct View {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl View {
+    /// A view of the entire image
+    pub fn full((image_width, image_height): (usize, usize)) -> View {
+        View {
+            x: 0,
+            y: 0,
+            width: image_width,
+            height: image_height,
+        }
+    }
+
+    pub fn is_full(&self, image_size: (usize, usize)) -> bool {
+        *self == View::full(image_size)
+    }
+
+    /// Scales the view by `factor` about the image pixel `about`, so that `about` stays at the same place in the view. A factor greater than one zooms in. The view keeps its aspect ratio and stays inside the image.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    pub fn zoom(
+        self,
+        (image_width, image_height): (usize, usize),
+        (about_x, about_y): (usize, usize),
+        factor: f64,
+    ) -> View {
+        if [image_width, image_height, self.width, self.height].contains(&0) {
+            return View::full((image_width, image_height));
+        }
+        let min_width = MIN_VIEW_SIZE.min(image_width) as f64;
+        let width = (self.width as f64 / factor).clamp(min_width, image_width as f64);
+        let height =
+            (self.height as f64 * width / self.width as f64).clamp(1.0, image_height as f64);
+
+        let x = about_x as f64 - (about_x as f64 - self.x as f64) * width / self.width as f64;
+        let y = about_y as f64 - (about_y as f64 - self.y as f64) * height / self.height as f64;
+
+        let width = width.round() as usize;
+        let height = height.round() as usize;
+        View {
+            x: (x.round().max(0.0) as usize).min(image_width.saturating_sub(width)),
+            y: (y.round().max(0.0) as usize).min(image_height.saturating_sub(height)),
+            width,
+            height,
+        }
+    }
+
+    /// Moves the view by the given number of image pixels, keeping it inside the image. Views larger than the image shrink to fit it.
+    pub fn pan(
+        self,
+        (image_width, image_height): (usize, usize),
+        (dx, dy): (isize, isize),
+    ) -> View {
+        let width = self.width.min(image_width);
+        let height = self.height.min(image_height);
+        View {
+            x: self
+                .x
+                .saturating_add_signed(dx)
+                .min(image_width - width),
+            y: self
+                .y
+                .saturating_add_signed(dy)
+                .min(image_height - height),
+            width,
+            height,
+        }
+    }
+
+    pub fn contains(&self, (x, y): (usize, usize)) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    /// Converts an index into an image `outer_cols` pixels wide to an index into the view, or `None` if the pixel is outside of the view
+    pub fn outer_index_to_inner_index(
+        &self,
+        outer_cols: usize,
+        outer_index: usize,
+    ) -> Option<usize> {
+        let outer_row = outer_index / outer_cols;
+        let outer_col = outer_index % outer_cols;
+        let inner_row = outer_row.checked_sub(self.y)?;
+        let inner_col = outer_col.checked_sub(self.x)?;
+
+        if inner_row >= self.height || inner_col >= self.width {
+            return None;
+        }
+
+        Some(inner_row * self.width + inner_col)
+    }
+
+    /// Converts an index into the view to an index into an image `outer_cols` pixels wide, or `None` if the index is outside of the view
+    pub fn inner_index_to_outer_index(
+        &self,
+        outer_cols: usize,
+        inner_index: usize,
+    ) -> Option<usize> {
+        if inner_index >= self.width * self.height {
+            return None;
+        }
+
+        let inner_row = inner_index / self.width;
+        let inner_col = inner_index % self.width;
+
+        Some((self.y + inner_row) * outer_cols + self.x + inner_col)
+    }
+}
+
+/// Something that pixels can be assigned to, in the order that the user is asked to assign them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssignmentTarget {
+    pub label: String,
+    pub pixel: Pixel,
+    /// The color of the face that the target is on
+    pub color: ArcIntern<str>,
+}
+
+/// Lists every sticker followed by the white balance of every face
+pub fn assignment_targets(puzzle_geometry: &PuzzleGeometry) -> Vec<AssignmentTarget> {
+    let stickers_to_assign = puzzle_geometry.non_fixed_stickers();
+
+    let mut targets: Vec<_> = stickers_to_assign
+        .iter()
+        .enumerate()
+        .map(|(i, (face, sticker))| AssignmentTarget {
+            label: format!(
+                "{} {}",
+                sticker
+                    .iter()
+                    .map(std::string::ToString::to_string)
+                    .collect::<String>(),
+                face.color
+            ),
+            pixel: Pixel::Sticker(i),
+            color: face.color.clone(),
+        })
+        .collect();
+
+    let mut white_balances_to_assign: Vec<_> = stickers_to_assign
+        .iter()
+        .map(|(face, _)| face.color.clone())
+        .collect();
+    white_balances_to_assign.dedup();
+
+    targets.extend(
+        white_balances_to_assign
+            .into_iter()
+            .map(|color| AssignmentTarget {
+                label: format!("{color} WB"),
+                pixel: Pixel::WhiteBalance(color.clone()),
+                color,
+            }),
+    );
+
+    targets
+}
+
+/// Finds the first target at or after `from`, wrapping around, that has no pixels assigned to it
+pub fn next_unassigned_target(
+    pixel_assignment: &[Pixel],
+    targets: &[AssignmentTarget],
+    from: usize,
+) -> Option<usize> {
+    let assigned: HashSet<&Pixel> = pixel_assignment.iter().collect();
+    (0..targets.len())
+        .map(|offset| (from + offset) % targets.len())
+        .find(|&i| !assigned.contains(&targets[i].pixel))
+}
+
+/// An approximation of how a face color looks, for drawing the overview of an assignment
+pub fn face_color_rgb(color: &str) -> [u8; 3] {
+    match color {
+        "white" => [255, 255, 255],
+        "yellow" => [255, 255, 0],
+        "red" => [255, 0, 0],
+        "orange" => [255, 128, 0],
+        "green" => [0, 200, 0],
+        "blue" => [0, 64, 255],
+        "purple" => [160, 0, 255],
+        "pink" => [255, 128, 192],
+        "gray" | "grey" => [128, 128, 128],
+        "black" => [32, 32, 32],
+        _ => {
+            // Pick an arbitrary but consistent color for anything unrecognized
+            let hash = color
+                .bytes()
+                .fold(0x811c_9dc5_u32, |hash, byte| {
+                    (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+                })
+                .to_be_bytes();
+            [hash[0], hash[1], hash[2]]
+        }
+    }
+}
+
+/// The pixels assigned to one target, for drawing the overview of an assignment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverviewRegion {
+    /// The index of the target in the list of targets
+    pub target_idx: usize,
+    pub rgb: [u8; 3],
+    /// The assigned pixels as indices into the image
+    pub pixels: Vec<usize>,
+    /// The average position of the assigned pixels, where the index of the target is drawn
+    pub centroid: (usize, usize),
+}
+
+/// Groups the assigned pixels of an image `width` pixels wide by target, skipping targets without any pixels
+pub fn overview_regions(
+    pixel_assignment: &[Pixel],
+    targets: &[AssignmentTarget],
+    width: usize,
+) -> Vec<OverviewRegion> {
+    let target_idxs: HashMap<&Pixel, usize> = targets
+        .iter()
+        .enumerate()
+        .map(|(i, target)| (&target.pixel, i))
+        .collect();
+
+    let mut pixels_by_target = vec![Vec::new(); targets.len()];
+    for (i, pixel) in pixel_assignment.iter().enumerate() {
+        if let Some(&target_idx) = target_idxs.get(pixel) {
+            pixels_by_target[target_idx].push(i);
+        }
+    }
+
+    pixels_by_target
+        .into_iter()
+        .enumerate()
+        .filter(|(_, pixels)| !pixels.is_empty())
+        .map(|(target_idx, pixels)| {
+            let (x_sum, y_sum) = pixels.iter().fold((0, 0), |(x_sum, y_sum), i| {
+                (x_sum + i % width, y_sum + i / width)
+            });
+            OverviewRegion {
+                target_idx,
+                rgb: face_color_rgb(&targets[target_idx].color),
+                centroid: (x_sum / pixels.len(), y_sum / pixels.len()),
+                pixels,
+            }
+        })
+        .collect()
+}
+
+/// Lists every sticker and white balance face whose pixels differ between the two assignments
+pub fn changed_targets(old: &[Pixel], new: &[Pixel]) -> Vec<Pixel> {
+    let changed: HashSet<&Pixel> = old
+        .iter()
+        .zip(new)
+        .filter(|(old, new)| old != new)
+        .flat_map(|(old, new)| [old, new])
+        .filter(|pixel| **pixel != Pixel::Unassigned)
+        .collect();
+    changed.into_iter().cloned().collect()
+}
+
+/// Describes what each value of a pixel assignment label map stands for. A pixel assignment is saved as a 16 bit grayscale PNG label map alongside this legend as JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PixelAssignmentLegend {
+    pub width: usize,
+    pub height: usize,
+    /// The label with value `i` stands for `labels[i]`. The label `0` is always `Pixel::Unassigned`.
+    pub labels: Vec<Pixel>,
+}
+
+/// Converts a pixel assignment of an image `width` pixels wide to a label map and its legend
+///
+/// # Panics
+///
+/// Panics if there are more than `u16::MAX` distinct assignments
+pub fn to_label_map(
+    pixel_assignment: &[Pixel],
+    width: usize,
+) -> (Box<[u16]>, PixelAssignmentLegend) {
+    let mut labels = vec![Pixel::Unassigned];
+    let mut label_values = HashMap::from([(Pixel::Unassigned, 0)]);
+
+    let label_map = pixel_assignment
+        .iter()
+        .map(|pixel| {
+            *label_values.entry(pixel.clone()).or_insert_with(|| {
+                labels.push(pixel.clone());
+                u16::try_from(labels.len() - 1).expect("Too many distinct pixel assignments")
+            })
+        })
+        .collect();
+
+    (
+        label_map,
+        PixelAssignmentLegend {
+            width,
+            height: pixel_assignment.len().checked_div(width).unwrap_or(0),
+            labels,
+        },
+    )
+}
+
+/// Converts a label map and its legend back to a pixel assignment
+///
+/// # Errors
+///
+/// Returns an error if the label map doesn't match the dimensions in the legend or contains a label that isn't in the legend
+pub fn from_label_map(
+    label_map: &[u16],
+    legend: &PixelAssignmentLegend,
+) -> Result<Box<[Pixel]>, String> {
+    if label_map.len() != legend.width * legend.height {
+        return Err(format!(
+            "Label map has {} pixels but the legend says it is {}x{}",
+            label_map.len(),
+            legend.width,
+            legend.height
+        ));
+    }
+
+    label_map
+        .iter()
+        .map(|&label| {
+            legend
+                .labels
+                .get(usize::from(label))
+                .cloned()
+                .ok_or_else(|| format!("Label {label} is not in the legend"))
+        })
+        .collect()
+}
+
+fn c(x: i32, n: i32) -> i32 {
+    (x + n) / 6
+}
+
+fn perm6_from_number(mut n: u16) -> [i32; 6] {
+    const FACT: [u16; 7] = [1, 1, 2, 6, 24, 120, 720];
+    n %= FACT[6];
+
+    let mut elems = vec![0, 1, 2, 3, 4, 5];
+    let mut result = [0; 6];
+
+    for i in 0..6 {
+        let f = FACT[5 - i];
+        let idx = (n / f) as usize;
+        n %= f;
+
+        result[i] = elems.remove(idx);
+    }
+
+    result
+}
+
+/// Computes the lower and upper per-channel flood fill tolerances for a drag from `drag_origin` to `drag_xy`. Dragging further loosens the tolerances and the direction of the drag shuffles them between the channels.
+pub fn flood_fill_tolerances(
+    (drag_origin_x, drag_origin_y): (i32, i32),
+    (drag_x, drag_y): (i32, i32),
+    upper_flood_fill_diff: i32,
+) -> ([i32; 3], [i32; 3]) {
+    #[allow(clippy::cast_possible_truncation)]
+    let distance = (f64::from(drag_x - drag_origin_x)
+        .hypot(f64::from(drag_y - drag_origin_y))
+        .powf(1.5)
+        / 15.0) as i32;
+    // angle is between [-pi, pi]; add pi and multiply by 360/pi to get a range
+    // of [0, 720] throughout the full circle which is 6!
+    //
+    // multiply it again by 20 to increase the periodicity
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let angle = (f64::from(drag_y - drag_origin_y).atan2(f64::from(drag_x - drag_origin_x))
+        + std::f64::consts::PI * 360.0 / std::f64::consts::PI * 20.0) as u16;
+    let perm6 = perm6_from_number(angle);
+
+    let upper = upper_flood_fill_diff * MAX_PIXEL_VALUE / UPPER_DIFF_MINDEFMAX[2];
+
+    (
+        [
+            c(distance, perm6[0]),
+            c(distance, perm6[1]),
+            c(distance, perm6[2]),
+        ],
+        [
+            c(distance, perm6[3] + upper),
+            c(distance, perm6[4] + upper),
+            c(distance, perm6[5] + upper),
+        ],
+    )
+}
@@ -0,0 +1,389 @@
+use crate::pixel_assignment::View;
+
+/// An axis aligned rectangle of pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    /// The smallest rectangle containing both corners
+    pub fn from_corners((x1, y1): (usize, usize), (x2, y2): (usize, usize)) -> Rect {
+        Rect {
+            x: x1.min(x2),
+            y: y1.min(y2),
+            width: x1.abs_diff(x2) + 1,
+            height: y1.abs_diff(y2) + 1,
+        }
+    }
+
+    pub fn contains(&self, (x, y): (usize, usize)) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+}
+
+impl From<View> for Rect {
+    fn from(
+        View {
+            x,
+            y,
+            width,
+            height,
+        }: View,
+    ) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+impl From<Rect> for View {
+    fn from(
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }: Rect,
+    ) -> View {
+        View {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+/// An elliptical structuring element, equivalent to `getStructuringElement(MORPH_ELLIPSE, ...)` in `OpenCV`
+pub struct Kernel {
+    offsets: Vec<(isize, isize)>,
+}
+
+impl Kernel {
+    #[allow(
+        clippy::cast_possible_wrap,
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss
+    )]
+    pub fn ellipse(size: usize) -> Kernel {
+        let size = size as isize;
+        let r = size / 2;
+        let inv_r2 = if r == 0 {
+            0.0
+        } else {
+            ((r * r) as f64).recip()
+        };
+
+        let mut offsets = Vec::new();
+        for i in 0..size {
+            let dy = i - r;
+            if dy.abs() > r {
+                continue;
+            }
+            let dx = (r as f64 * (((r * r - dy * dy) as f64) * inv_r2).sqrt()).round() as isize;
+            for j in (r - dx).max(0)..(r + dx + 1).min(size) {
+                offsets.push((j - r, dy));
+            }
+        }
+
+        Kernel { offsets }
+    }
+}
+
+/// A binary image the same size as the image being assigned
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mask {
+    width: usize,
+    height: usize,
+    data: Box<[bool]>,
+}
+
+impl Mask {
+    pub fn new(width: usize, height: usize) -> Mask {
+        Mask {
+            width,
+            height,
+            data: vec![false; width * height].into_boxed_slice(),
+        }
+    }
+
+    pub fn get(&self, (x, y): (usize, usize)) -> bool {
+        self.data[y * self.width + x]
+    }
+
+    pub fn set(&mut self, (x, y): (usize, usize), value: bool) {
+        self.data[y * self.width + x] = value;
+    }
+
+    pub fn count(&self) -> usize {
+        self.data.iter().filter(|v| **v).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.data.iter().any(|v| *v)
+    }
+
+    /// The indices of the set pixels, in row major order
+    pub fn indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.data
+            .iter()
+            .enumerate()
+            .filter_map(|(i, v)| if *v { Some(i) } else { None })
+    }
+
+    /// Sets every pixel that is set in `other`
+    pub fn union(&mut self, other: &Mask) {
+        for (value, other) in self.data.iter_mut().zip(&other.data) {
+            *value |= *other;
+        }
+    }
+
+    /// Unsets every pixel that is set in `other`
+    pub fn subtract(&mut self, other: &Mask) {
+        for (value, other) in self.data.iter_mut().zip(&other.data) {
+            *value &= !*other;
+        }
+    }
+
+    /// Sets every pixel of `rect` to `value`
+    pub fn fill_rect(&mut self, rect: Rect, value: bool) {
+        for y in rect.y..(rect.y + rect.height).min(self.height) {
+            for x in rect.x..(rect.x + rect.width).min(self.width) {
+                self.set((x, y), value);
+            }
+        }
+    }
+
+    /// Sets every pixel within `diameter / 2` of the line from `from` to `to` to `value`, like a stroke of a round brush
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    pub fn fill_line(
+        &mut self,
+        from: (usize, usize),
+        to: (usize, usize),
+        diameter: usize,
+        value: bool,
+    ) {
+        let radius = diameter as f64 / 2.0;
+        let (from_x, from_y) = (from.0 as f64, from.1 as f64);
+        let (dx, dy) = (to.0 as f64 - from_x, to.1 as f64 - from_y);
+        let length_squared = dx * dx + dy * dy;
+
+        let min_x = (from_x.min(to.0 as f64) - radius).floor().max(0.0) as usize;
+        let min_y = (from_y.min(to.1 as f64) - radius).floor().max(0.0) as usize;
+        let max_x = ((from_x.max(to.0 as f64) + radius).ceil() as usize).min(self.width - 1);
+        let max_y = ((from_y.max(to.1 as f64) + radius).ceil() as usize).min(self.height - 1);
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                let (px, py) = (x as f64 - from_x, y as f64 - from_y);
+                let t = if length_squared == 0.0 {
+                    0.0
+                } else {
+                    ((px * dx + py * dy) / length_squared).clamp(0.0, 1.0)
+                };
+                if (px - t * dx).hypot(py - t * dy) <= radius {
+                    self.set((x, y), value);
+                }
+            }
+        }
+    }
+
+    /// Sets every pixel whose center is inside the polygon to `value`, using the even-odd rule
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    pub fn fill_polygon(&mut self, vertices: &[(usize, usize)], value: bool) {
+        if vertices.len() < 3 {
+            return;
+        }
+        let mut crossings = Vec::new();
+        for y in 0..self.height {
+            let center_y = y as f64 + 0.5;
+            crossings.clear();
+            for (i, &(x1, y1)) in vertices.iter().enumerate() {
+                let (x2, y2) = vertices[(i + 1) % vertices.len()];
+                let (x1, y1, x2, y2) = (
+                    x1 as f64 + 0.5,
+                    y1 as f64 + 0.5,
+                    x2 as f64 + 0.5,
+                    y2 as f64 + 0.5,
+                );
+                if (y1 <= center_y) != (y2 <= center_y) {
+                    crossings.push(x1 + (center_y - y1) / (y2 - y1) * (x2 - x1));
+                }
+            }
+            crossings.sort_by(f64::total_cmp);
+            for pair in crossings.chunks_exact(2) {
+                let start = (pair[0] - 0.5).ceil().max(0.0) as usize;
+                let end = ((pair[1] - 0.5).ceil().max(0.0) as usize).min(self.width);
+                for x in start..end {
+                    self.set((x, y), value);
+                }
+            }
+        }
+    }
+
+    fn neighbor(&self, (x, y): (usize, usize), (dx, dy): (isize, isize)) -> Option<(usize, usize)> {
+        let x = x.checked_add_signed(dx)?;
+        let y = y.checked_add_signed(dy)?;
+        if x < self.width && y < self.height {
+            Some((x, y))
+        } else {
+            None
+        }
+    }
+
+    /// Morphological erosion. Pixels outside of the image count as unset.
+    pub fn erode(&self, kernel: &Kernel, iterations: usize) -> Mask {
+        let mut current = self.clone();
+        for _ in 0..iterations {
+            let mut next = Mask::new(self.width, self.height);
+            for i in current.indices() {
+                let xy = (i % self.width, i / self.width);
+                if kernel.offsets.iter().all(|&offset| {
+                    current
+                        .neighbor(xy, offset)
+                        .is_some_and(|neighbor| current.get(neighbor))
+                }) {
+                    next.data[i] = true;
+                }
+            }
+            current = next;
+        }
+        current
+    }
+
+    /// Morphological dilation
+    pub fn dilate(&self, kernel: &Kernel) -> Mask {
+        let mut ret = Mask::new(self.width, self.height);
+        for i in self.indices() {
+            let xy = (i % self.width, i / self.width);
+            for &offset in &kernel.offsets {
+                if let Some(neighbor) = self.neighbor(xy, offset) {
+                    ret.set(neighbor, true);
+                }
+            }
+        }
+        ret
+    }
+
+    /// The approximate euclidean distance of every set pixel to the nearest unset pixel, using a two pass chamfer. Pixels outside of the image count as unset and unset pixels have distance zero.
+    pub fn distance_transform(&self) -> Vec<f64> {
+        const STRAIGHT: f64 = 1.0;
+        const DIAGONAL: f64 = std::f64::consts::SQRT_2;
+
+        let mut distances: Vec<f64> = self
+            .data
+            .iter()
+            .map(|v| if *v { f64::INFINITY } else { 0.0 })
+            .collect();
+        let distance_at = |distances: &[f64], xy: (usize, usize), offset: (isize, isize)| {
+            self.neighbor(xy, offset)
+                .map_or(0.0, |(x, y)| distances[y * self.width + x])
+        };
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let i = y * self.width + x;
+                if distances[i] == 0.0 {
+                    continue;
+                }
+                distances[i] = distances[i]
+                    .min(distance_at(&distances, (x, y), (-1, 0)) + STRAIGHT)
+                    .min(distance_at(&distances, (x, y), (0, -1)) + STRAIGHT)
+                    .min(distance_at(&distances, (x, y), (-1, -1)) + DIAGONAL)
+                    .min(distance_at(&distances, (x, y), (1, -1)) + DIAGONAL);
+            }
+        }
+        for y in (0..self.height).rev() {
+            for x in (0..self.width).rev() {
+                let i = y * self.width + x;
+                if distances[i] == 0.0 {
+                    continue;
+                }
+                distances[i] = distances[i]
+                    .min(distance_at(&distances, (x, y), (1, 0)) + STRAIGHT)
+                    .min(distance_at(&distances, (x, y), (0, 1)) + STRAIGHT)
+                    .min(distance_at(&distances, (x, y), (1, 1)) + DIAGONAL)
+                    .min(distance_at(&distances, (x, y), (-1, 1)) + DIAGONAL);
+            }
+        }
+        distances
+    }
+
+    /// The 4-connected region of set pixels containing `seed`
+    pub fn connected_component(&self, seed: (usize, usize)) -> Mask {
+        let mut ret = Mask::new(self.width, self.height);
+        if !self.get(seed) {
+            return ret;
+        }
+        let mut stack = vec![seed];
+        ret.set(seed, true);
+        while let Some(xy) = stack.pop() {
+            for offset in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+                if let Some(neighbor) = self.neighbor(xy, offset)
+                    && self.get(neighbor)
+                    && !ret.get(neighbor)
+                {
+                    ret.set(neighbor, true);
+                    stack.push(neighbor);
+                }
+            }
+        }
+        ret
+    }
+}
+
+/// Flood fills from `seed` using a fixed range relative to the seed color and 4-connectivity, like `floodFill` with `FLOODFILL_FIXED_RANGE` in `OpenCV`. The fill never leaves `bounds`.
+pub fn flood_fill(
+    image: &[[u8; 3]],
+    width: usize,
+    height: usize,
+    bounds: Rect,
+    seed: (usize, usize),
+    lower_diff: [i32; 3],
+    upper_diff: [i32; 3],
+) -> Mask {
+    let mut ret = Mask::new(width, height);
+    if !bounds.contains(seed) {
+        return ret;
+    }
+
+    let seed_color = image[seed.1 * width + seed.0];
+    let within_range = |(x, y): (usize, usize)| {
+        let color = image[y * width + x];
+        (0..3).all(|c| {
+            let value = i32::from(color[c]);
+            let seed_value = i32::from(seed_color[c]);
+            seed_value - lower_diff[c] <= value && value <= seed_value + upper_diff[c]
+        })
+    };
+
+    let mut stack = vec![seed];
+    ret.set(seed, true);
+    while let Some(xy) = stack.pop() {
+        for offset in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+            if let Some(neighbor) = ret.neighbor(xy, offset)
+                && bounds.contains(neighbor)
+                && !ret.get(neighbor)
+                && within_range(neighbor)
+            {
+                ret.set(neighbor, true);
+                stack.push(neighbor);
+            }
+        }
+    }
+    ret
+}
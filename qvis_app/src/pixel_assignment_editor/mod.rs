@@ -0,0 +1,1098 @@
+use crate::pixel_assignment::{
+    AssignmentTarget, BRUSH_SIZE_MINDEFMAX, ERODE_UNTIL_PERCENT_MINDEFMAX, EROSION_SIZE_MINDEFMAX,
+    MAX_PIXEL_VALUE, MIN_SAMPLES_MINDEFMAX, NUM_SAMPLES_MINDEFMAX, OverviewRegion, SamplingConfig,
+    SamplingStrategy, SelectionTool, UPPER_DIFF_MINDEFMAX, View, ZOOM_STEP, assignment_targets,
+    border_weight, flood_fill_tolerances, inference_cost_text, next_unassigned_target,
+    overview_regions,
+};
+use leptos::{html, prelude::*};
+use log::{info, warn};
+use mask::{Kernel, Mask, Rect, flood_fill};
+use puzzle_theory::puzzle_geometry::PuzzleGeometry;
+use qvis::Pixel;
+use rand::{SeedableRng, rngs::SmallRng};
+use std::sync::Arc;
+use wasm_bindgen::{Clamped, JsCast};
+
+mod mask;
+
+const MAX_EROSIONS: usize = 5;
+
+/// A still frame of the camera to assign pixels on
+#[derive(Debug, Clone)]
+pub struct AssignmentImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Box<[[u8; 3]]>,
+}
+
+struct Selection {
+    cleaned: Mask,
+    eroded: Mask,
+    samples: Vec<usize>,
+    /// How much each of `samples` counts during inference, based on its distance to the border of `cleaned`
+    weights: Vec<f64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum CropState {
+    NoCrop,
+    SelectingCrop(Option<((usize, usize), (usize, usize))>),
+    Crop(Rect),
+}
+
+struct EditorState {
+    image: AssignmentImage,
+    pixel_assignment: Box<[Pixel]>,
+    pixel_weights: Box<[f64]>,
+    targets: Vec<AssignmentTarget>,
+    assigning_idx: usize,
+    erosion_size: usize,
+    upper_flood_fill_diff: i32,
+    sampling: SamplingConfig,
+    drag_origin: Option<(usize, usize)>,
+    drag_xy: Option<(usize, usize)>,
+    dragging: bool,
+    crop: CropState,
+    selection: Option<Selection>,
+    overview: bool,
+    pan_anchor: Option<(usize, usize)>,
+    tool: SelectionTool,
+    combine_flood_fill: bool,
+    brush_size: usize,
+    /// The pixels selected with the polygon, rectangle and brush tools
+    manual: Mask,
+    /// The pixels removed from the selection with the eraser
+    erased: Mask,
+    /// The vertices of the polygon being drawn
+    polygon: Vec<(usize, usize)>,
+    /// The corner of the rectangle being drawn
+    rect_anchor: Option<(usize, usize)>,
+    /// Where the brush or eraser last painted while the pointer is down
+    brush_xy: Option<(usize, usize)>,
+    /// Where the pointer is, for previewing the polygon, rectangle and brush tools
+    hover_xy: Option<(usize, usize)>,
+}
+
+impl EditorState {
+    #[allow(clippy::cast_sign_loss)]
+    fn new(
+        image: AssignmentImage,
+        targets: Vec<AssignmentTarget>,
+        initial: Option<Box<[Pixel]>>,
+    ) -> EditorState {
+        let (width, height) = (image.width, image.height);
+        let pixel_count = width * height;
+        let pixel_assignment = match initial {
+            Some(initial) if initial.len() == pixel_count => initial,
+            Some(initial) => {
+                warn!(
+                    "Ignoring initial pixel assignment with {} pixels for an image with {pixel_count}",
+                    initial.len()
+                );
+                vec![Pixel::Unassigned; pixel_count].into_boxed_slice()
+            }
+            None => vec![Pixel::Unassigned; pixel_count].into_boxed_slice(),
+        };
+        EditorState {
+            assigning_idx: next_unassigned_target(&pixel_assignment, &targets, 0).unwrap_or(0),
+            pixel_assignment,
+            pixel_weights: vec![1.0; pixel_count].into_boxed_slice(),
+            image,
+            targets,
+            erosion_size: EROSION_SIZE_MINDEFMAX[1] as usize,
+            upper_flood_fill_diff: UPPER_DIFF_MINDEFMAX[1],
+            sampling: SamplingConfig::default(),
+            drag_origin: None,
+            drag_xy: None,
+            dragging: false,
+            crop: CropState::NoCrop,
+            selection: None,
+            overview: false,
+            pan_anchor: None,
+            tool: SelectionTool::default(),
+            combine_flood_fill: false,
+            brush_size: BRUSH_SIZE_MINDEFMAX[1] as usize,
+            manual: Mask::new(width, height),
+            erased: Mask::new(width, height),
+            polygon: Vec::new(),
+            rect_anchor: None,
+            brush_xy: None,
+            hover_xy: None,
+        }
+    }
+
+    fn view_rect(&self) -> Rect {
+        match self.crop {
+            CropState::Crop(rect) => rect,
+            CropState::NoCrop | CropState::SelectingCrop(_) => Rect {
+                x: 0,
+                y: 0,
+                width: self.image.width,
+                height: self.image.height,
+            },
+        }
+    }
+
+    fn image_size(&self) -> (usize, usize) {
+        (self.image.width, self.image.height)
+    }
+
+    /// Shows the given part of the image, dropping the drag if it started outside of it
+    fn set_view(&mut self, view: View) {
+        let rect = Rect::from(view);
+        self.crop = if view.is_full(self.image_size()) {
+            CropState::NoCrop
+        } else {
+            CropState::Crop(rect)
+        };
+        if !self.drag_origin.is_some_and(|origin| rect.contains(origin)) {
+            self.clear_drag();
+        }
+        self.update_selection();
+    }
+
+    /// Zooms in by `factor` (or out if it is less than one) while keeping the image pixel `about` under the pointer
+    fn zoom(&mut self, about: (usize, usize), factor: f64) {
+        if let CropState::SelectingCrop(_) = self.crop {
+            return;
+        }
+        let view = View::from(self.view_rect());
+        let zoomed = view.zoom(self.image_size(), about, factor);
+        if zoomed != view {
+            self.set_view(zoomed);
+        }
+    }
+
+    /// Moves the view so that the image pixel grabbed when panning started is at `xy` in the current view
+    #[allow(clippy::cast_possible_wrap)]
+    fn pan_to(&mut self, xy: (usize, usize)) {
+        let Some((anchor_x, anchor_y)) = self.pan_anchor else {
+            return;
+        };
+        let view = View::from(self.view_rect());
+        let panned = view.pan(
+            self.image_size(),
+            (
+                anchor_x as isize - xy.0 as isize,
+                anchor_y as isize - xy.1 as isize,
+            ),
+        );
+        if panned != view {
+            self.set_view(panned);
+        }
+    }
+
+    fn handle_radius(&self) -> usize {
+        (self.view_rect().width / 100).max(2)
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    fn update_selection(&mut self) {
+        let erosion_kernel = Kernel::ellipse(self.erosion_size);
+        let maybe_drag = match (self.drag_origin, self.drag_xy) {
+            (Some(drag_origin), Some(drag_xy))
+                if self.tool.uses_flood_fill(self.combine_flood_fill) =>
+            {
+                Some((drag_origin, drag_xy))
+            }
+            _ => None,
+        };
+
+        let mut cleaned = match maybe_drag {
+            Some((drag_origin, drag_xy)) => self.flood_fill_selection(drag_origin, drag_xy),
+            None => Mask::new(self.image.width, self.image.height),
+        };
+        if self.tool.uses_manual_selection(self.combine_flood_fill) {
+            cleaned.union(&self.manual);
+            cleaned.subtract(&self.erased);
+        }
+        if cleaned.is_empty() {
+            self.selection = None;
+            return;
+        }
+
+        let eroded = erode_until_enough(&cleaned, &erosion_kernel, &self.sampling);
+
+        let (seed_x, seed_y) = maybe_drag.map_or((0, 0), |(drag_origin, _)| drag_origin);
+        let mut seed = [0; 32];
+        seed[0..8].copy_from_slice(&(seed_x as u64).to_be_bytes());
+        seed[8..16].copy_from_slice(&(seed_y as u64).to_be_bytes());
+        let mut rng = SmallRng::from_seed(seed);
+        let mut nonzeroes: Vec<usize> = eroded.indices().collect();
+        let samples = self
+            .sampling
+            .sample(&mut nonzeroes, self.image.width, &mut rng);
+
+        let distances = cleaned.distance_transform();
+        let max_distance = distances.iter().copied().fold(0.0, f64::max);
+        let weights = samples
+            .iter()
+            .map(|&i| border_weight(distances[i], max_distance))
+            .collect();
+
+        self.selection = Some(Selection {
+            cleaned,
+            eroded,
+            samples,
+            weights,
+        });
+    }
+
+    /// Flood fills from `drag_origin` with a tolerance set by the drag and cleans up the result
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    fn flood_fill_selection(&self, drag_origin: (usize, usize), drag_xy: (usize, usize)) -> Mask {
+        let (lower_diff, upper_diff) = flood_fill_tolerances(
+            (drag_origin.0 as i32, drag_origin.1 as i32),
+            (drag_xy.0 as i32, drag_xy.1 as i32),
+            self.upper_flood_fill_diff,
+        );
+        let erosion_kernel = Kernel::ellipse(self.erosion_size);
+        let erosion_kernel_times_two = Kernel::ellipse(self.erosion_size * 2);
+
+        let filled = flood_fill(
+            &self.image.pixels,
+            self.image.width,
+            self.image.height,
+            self.view_rect(),
+            drag_origin,
+            lower_diff,
+            upper_diff,
+        );
+
+        let mut cleaned = filled.erode(&erosion_kernel, 2);
+        if cleaned.is_empty() {
+            filled
+        } else {
+            cleaned.set(drag_origin, true);
+            cleaned
+                .connected_component(drag_origin)
+                .dilate(&erosion_kernel_times_two)
+        }
+    }
+
+    fn clear_drag(&mut self) {
+        self.drag_origin = None;
+        self.drag_xy = None;
+        self.dragging = false;
+        self.selection = None;
+    }
+
+    /// Forgets the flood fill and everything selected with the other tools
+    fn clear_selection(&mut self) {
+        self.clear_drag();
+        self.manual = Mask::new(self.image.width, self.image.height);
+        self.erased = Mask::new(self.image.width, self.image.height);
+        self.polygon.clear();
+        self.rect_anchor = None;
+        self.brush_xy = None;
+    }
+
+    fn set_tool(&mut self, tool: SelectionTool) {
+        self.tool = tool;
+        self.polygon.clear();
+        self.rect_anchor = None;
+        self.brush_xy = None;
+        self.update_selection();
+    }
+
+    fn set_sampling(&mut self, f: impl FnOnce(&mut SamplingConfig)) {
+        f(&mut self.sampling);
+        self.update_selection();
+    }
+
+    fn toggle_combine_flood_fill(&mut self) {
+        self.combine_flood_fill = !self.combine_flood_fill;
+        self.update_selection();
+    }
+
+    /// Paints a stroke of the brush or eraser
+    fn paint(&mut self, from: (usize, usize), to: (usize, usize)) {
+        let erasing = self.tool == SelectionTool::Eraser;
+        self.manual.fill_line(from, to, self.brush_size, !erasing);
+        self.erased.fill_line(from, to, self.brush_size, erasing);
+        self.brush_xy = Some(to);
+        self.update_selection();
+    }
+
+    /// Fills the polygon being drawn into the selection
+    fn close_polygon(&mut self) {
+        if self.polygon.len() < 3 {
+            return;
+        }
+        let vertices = std::mem::take(&mut self.polygon);
+        self.manual.fill_polygon(&vertices, true);
+        self.erased.fill_polygon(&vertices, false);
+        self.update_selection();
+    }
+
+    /// Unassigns every pixel assigned to the given target and returns how many there were
+    fn clear_target(&mut self, idx: usize) -> usize {
+        let target = &self.targets[idx];
+        let mut count = 0;
+        for pixel in &mut self.pixel_assignment {
+            if *pixel == target.pixel {
+                *pixel = Pixel::Unassigned;
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Assigns the sampled pixels to the current target, replacing whatever was assigned to it before, and moves to the next target without any pixels. Returns whether every target has been assigned.
+    fn assign(&mut self) -> bool {
+        if self.assigning_idx >= self.targets.len() {
+            return true;
+        }
+        self.clear_target(self.assigning_idx);
+        let target = &self.targets[self.assigning_idx];
+
+        let (samples, weights) = self
+            .selection
+            .as_ref()
+            .map(|selection| (selection.samples.as_slice(), selection.weights.as_slice()))
+            .unwrap_or_default();
+        for (&i, &weight) in samples.iter().zip(weights) {
+            self.pixel_assignment[i] = target.pixel.clone();
+            self.pixel_weights[i] = weight;
+        }
+        info!("Assigned {} pixels to {}", samples.len(), target.label);
+
+        self.clear_selection();
+        match next_unassigned_target(
+            &self.pixel_assignment,
+            &self.targets,
+            self.assigning_idx + 1,
+        ) {
+            Some(next_idx) => {
+                self.assigning_idx = next_idx;
+                false
+            }
+            None => true,
+        }
+    }
+
+    fn back(&mut self) {
+        if self.assigning_idx == 0 {
+            return;
+        }
+        self.assigning_idx -= 1;
+        self.clear_selection();
+    }
+
+    fn clear(&mut self) {
+        if self.assigning_idx >= self.targets.len() {
+            return;
+        }
+        let count = self.clear_target(self.assigning_idx);
+        info!("Cleared {count} pixels");
+
+        self.clear_selection();
+    }
+
+    fn toggle_overview(&mut self) {
+        self.overview = !self.overview;
+    }
+
+    fn overview_regions(&self) -> Vec<OverviewRegion> {
+        overview_regions(&self.pixel_assignment, &self.targets, self.image.width)
+    }
+
+    /// Switches to assigning the given target without changing any assignments
+    fn jump_to(&mut self, idx: usize) {
+        if idx < self.targets.len() && idx != self.assigning_idx {
+            self.assigning_idx = idx;
+            self.clear_selection();
+        }
+    }
+
+    fn toggle_crop(&mut self) {
+        self.crop = match self.crop {
+            CropState::NoCrop => CropState::SelectingCrop(None),
+            CropState::SelectingCrop(_) | CropState::Crop(_) => CropState::NoCrop,
+        };
+        self.update_selection();
+    }
+
+    fn pointer_down(&mut self, xy: (usize, usize)) {
+        if let CropState::SelectingCrop(_) = self.crop {
+            self.crop = CropState::SelectingCrop(Some((xy, xy)));
+            return;
+        }
+
+        match self.tool {
+            SelectionTool::FloodFill => {}
+            SelectionTool::Polygon => {
+                let closing = self.polygon.len() >= 3
+                    && self.polygon.first().is_some_and(|&(first_x, first_y)| {
+                        first_x.abs_diff(xy.0).max(first_y.abs_diff(xy.1)) <= self.handle_radius()
+                    });
+                if closing {
+                    self.close_polygon();
+                } else {
+                    self.polygon.push(xy);
+                }
+                return;
+            }
+            SelectionTool::Rectangle => {
+                self.rect_anchor = Some(xy);
+                return;
+            }
+            SelectionTool::Brush | SelectionTool::Eraser => {
+                self.paint(xy, xy);
+                return;
+            }
+        }
+
+        match self.drag_xy {
+            Some((drag_x, drag_y))
+                if drag_x.abs_diff(xy.0).max(drag_y.abs_diff(xy.1)) <= self.handle_radius() => {}
+            _ => self.drag_origin = Some(xy),
+        }
+        self.drag_xy = Some(xy);
+        self.dragging = true;
+        self.update_selection();
+    }
+
+    fn pointer_move(&mut self, xy: (usize, usize)) {
+        self.hover_xy = Some(xy);
+        if let CropState::SelectingCrop(Some((anchor, _))) = self.crop {
+            self.crop = CropState::SelectingCrop(Some((anchor, xy)));
+        } else if let Some(from) = self.brush_xy {
+            self.paint(from, xy);
+        } else if self.dragging {
+            self.drag_xy = Some(xy);
+            self.update_selection();
+        }
+    }
+
+    /// Tracks the pointer while no button is held so that the polygon, rectangle and brush can be previewed
+    fn pointer_hover(&mut self, xy: (usize, usize)) {
+        self.hover_xy = Some(xy);
+    }
+
+    fn pointer_up(&mut self) {
+        if let Some(anchor) = self.rect_anchor.take()
+            && let Some(xy) = self.hover_xy
+        {
+            let rect = Rect::from_corners(anchor, xy);
+            self.manual.fill_rect(rect, true);
+            self.erased.fill_rect(rect, false);
+            self.update_selection();
+        }
+        self.brush_xy = None;
+
+        if let CropState::SelectingCrop(Some((anchor, xy))) = self.crop {
+            let rect = Rect::from_corners(anchor, xy);
+            if rect.width < 3 || rect.height < 3 {
+                self.crop = CropState::SelectingCrop(None);
+                return;
+            }
+            self.set_view(View::from(rect));
+        }
+        self.dragging = false;
+        self.pan_anchor = None;
+    }
+
+    #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+    fn render(&self) -> Vec<u8> {
+        const MAX: u8 = MAX_PIXEL_VALUE as u8;
+        const MAX_3_4: u8 = (MAX_PIXEL_VALUE * 3 / 4) as u8;
+
+        let view = self.view_rect();
+        let mut overlay: Vec<Option<[u8; 3]>> = vec![None; self.pixel_assignment.len()];
+        if let Some(selection) = &self.selection {
+            for i in selection.cleaned.indices() {
+                overlay[i] = Some([MAX, 0, MAX]);
+            }
+            for i in selection.eroded.indices() {
+                overlay[i] = Some([MAX_3_4, 0, MAX_3_4]);
+            }
+            for &i in &selection.samples {
+                overlay[i] = Some([MAX / 2, 0, MAX / 2]);
+            }
+        } else if self.overview {
+            let dot_radius = self.handle_radius() / 2;
+            for region in self.overview_regions() {
+                for &i in &region.pixels {
+                    let (x, y) = (i % self.image.width, i / self.image.width);
+                    for dot_y in y.saturating_sub(dot_radius)..=(y + dot_radius) {
+                        for dot_x in x.saturating_sub(dot_radius)..=(x + dot_radius) {
+                            if dot_x < self.image.width && dot_y < self.image.height {
+                                overlay[dot_y * self.image.width + dot_x] = Some(region.rgb);
+                            }
+                        }
+                    }
+                }
+            }
+        } else {
+            for (i, pixel) in self.pixel_assignment.iter().enumerate() {
+                if *pixel != Pixel::Unassigned {
+                    overlay[i] = Some([MAX, 0, MAX]);
+                }
+            }
+        }
+
+        let mut rgba = Vec::with_capacity(view.width * view.height * 4);
+        for y in view.y..view.y + view.height {
+            for x in view.x..view.x + view.width {
+                let i = y * self.image.width + x;
+                let [r, g, b] = overlay[i].unwrap_or(self.image.pixels[i]);
+                rgba.extend_from_slice(&[r, g, b, MAX]);
+            }
+        }
+        rgba
+    }
+
+    #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+    fn draw(&self, canvas: &web_sys::HtmlCanvasElement) {
+        let view = self.view_rect();
+        canvas.set_width(view.width as u32);
+        canvas.set_height(view.height as u32);
+        // Stretch the canvas to fill its container so that zooming in makes the image bigger rather than just cropping it
+        if let Some(parent) = canvas.parent_element() {
+            let scale = (f64::from(parent.client_width()) / view.width as f64)
+                .min(f64::from(parent.client_height()) / view.height as f64);
+            if scale > 0.0 {
+                let _ = canvas.set_attribute(
+                    "style",
+                    &format!(
+                        "width: {}px; height: {}px",
+                        (view.width as f64 * scale).floor(),
+                        (view.height as f64 * scale).floor()
+                    ),
+                );
+            }
+        }
+
+        let ctx = canvas
+            .get_context("2d")
+            .unwrap()
+            .unwrap()
+            .dyn_into::<web_sys::CanvasRenderingContext2d>()
+            .unwrap();
+        let image_data = web_sys::ImageData::new_with_u8_clamped_array_and_sh(
+            Clamped(&self.render()),
+            view.width as u32,
+            view.height as u32,
+        )
+        .unwrap();
+        ctx.put_image_data(&image_data, 0.0, 0.0).unwrap();
+
+        let to_view =
+            |(x, y): (usize, usize)| ((x - view.x) as f64 + 0.5, (y - view.y) as f64 + 0.5);
+
+        if let (Some(drag_origin), Some(drag_xy)) = (self.drag_origin, self.drag_xy)
+            && view.contains(drag_origin)
+            && view.contains(drag_xy)
+        {
+            let (origin_x, origin_y) = to_view(drag_origin);
+            let (drag_x, drag_y) = to_view(drag_xy);
+            let radius = self.handle_radius() as f64;
+
+            ctx.set_stroke_style_str("white");
+            ctx.set_line_width(radius / 2.0);
+            ctx.begin_path();
+            ctx.move_to(origin_x, origin_y);
+            ctx.line_to(drag_x, drag_y);
+            ctx.stroke();
+
+            ctx.set_fill_style_str("white");
+            ctx.begin_path();
+            ctx.arc(drag_x, drag_y, radius, 0.0, std::f64::consts::TAU)
+                .unwrap();
+            ctx.fill();
+            ctx.set_fill_style_str("red");
+            ctx.begin_path();
+            ctx.arc(drag_x, drag_y, radius * 0.6, 0.0, std::f64::consts::TAU)
+                .unwrap();
+            ctx.fill();
+        }
+
+        if self.overview && self.selection.is_none() {
+            ctx.set_font(&format!("bold {}px sans-serif", self.handle_radius() * 4));
+            ctx.set_line_width(self.handle_radius() as f64);
+            ctx.set_stroke_style_str("black");
+            ctx.set_fill_style_str("white");
+            for region in self.overview_regions() {
+                if !view.contains(region.centroid) {
+                    continue;
+                }
+                let (x, y) = to_view(region.centroid);
+                let text = (region.target_idx + 1).to_string();
+                ctx.stroke_text(&text, x, y).unwrap();
+                ctx.fill_text(&text, x, y).unwrap();
+            }
+        }
+
+        self.draw_tool(&ctx, view);
+
+        if let CropState::SelectingCrop(Some((anchor, xy))) = self.crop {
+            let rect = Rect::from_corners(anchor, xy);
+            ctx.set_stroke_style_str("cyan");
+            ctx.set_line_width(2.0);
+            ctx.stroke_rect(
+                rect.x as f64,
+                rect.y as f64,
+                rect.width as f64,
+                rect.height as f64,
+            );
+        }
+    }
+
+    /// Draws the polygon or rectangle being drawn, or the outline of the brush under the pointer
+    #[allow(clippy::cast_precision_loss)]
+    fn draw_tool(&self, ctx: &web_sys::CanvasRenderingContext2d, view: Rect) {
+        let to_view =
+            |(x, y): (usize, usize)| ((x - view.x) as f64 + 0.5, (y - view.y) as f64 + 0.5);
+        let hover_xy = self.hover_xy.filter(|&xy| view.contains(xy));
+        ctx.set_stroke_style_str("cyan");
+        ctx.set_line_width(self.handle_radius() as f64 / 2.0);
+        match self.tool {
+            SelectionTool::FloodFill => {}
+            SelectionTool::Polygon => {
+                let Some(&first) = self.polygon.first() else {
+                    return;
+                };
+                if !self.polygon.iter().all(|&xy| view.contains(xy)) {
+                    return;
+                }
+                ctx.begin_path();
+                let (first_x, first_y) = to_view(first);
+                ctx.move_to(first_x, first_y);
+                for &vertex in self.polygon.iter().skip(1).chain(&hover_xy) {
+                    let (x, y) = to_view(vertex);
+                    ctx.line_to(x, y);
+                }
+                ctx.stroke();
+                ctx.begin_path();
+                ctx.arc(
+                    first_x,
+                    first_y,
+                    self.handle_radius() as f64,
+                    0.0,
+                    std::f64::consts::TAU,
+                )
+                .unwrap();
+                ctx.stroke();
+            }
+            SelectionTool::Rectangle => {
+                if let Some(anchor) = self.rect_anchor
+                    && let Some(xy) = hover_xy
+                    && view.contains(anchor)
+                {
+                    let rect = Rect::from_corners(anchor, xy);
+                    let (x, y) = to_view((rect.x, rect.y));
+                    ctx.stroke_rect(x, y, rect.width as f64, rect.height as f64);
+                }
+            }
+            SelectionTool::Brush | SelectionTool::Eraser => {
+                if let Some(xy) = hover_xy {
+                    let (x, y) = to_view(xy);
+                    ctx.set_line_width(1.0);
+                    ctx.begin_path();
+                    ctx.arc(
+                        x,
+                        y,
+                        self.brush_size as f64 / 2.0,
+                        0.0,
+                        std::f64::consts::TAU,
+                    )
+                    .unwrap();
+                    ctx.stroke();
+                }
+            }
+        }
+    }
+}
+
+/// Erodes the mask until the next erosion would leave too few pixels and returns the last mask with enough pixels
+fn erode_until_enough(cleaned: &Mask, erosion_kernel: &Kernel, sampling: &SamplingConfig) -> Mask {
+    let og_num_pixels = cleaned.count();
+    let has_eroded_enough = |mask: &Mask| sampling.has_eroded_enough(og_num_pixels, mask.count());
+
+    if has_eroded_enough(cleaned) {
+        return cleaned.clone();
+    }
+
+    let mut previous = cleaned.clone();
+    for _ in 1..MAX_EROSIONS {
+        let eroded = previous.erode(erosion_kernel, 2);
+        if has_eroded_enough(&eroded) {
+            break;
+        }
+        previous = eroded;
+    }
+    previous
+}
+
+/// Converts a pointer or wheel position on the canvas to a pixel of the image
+#[allow(
+    clippy::cast_sign_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss
+)]
+fn pointer_to_image(
+    canvas: &web_sys::HtmlCanvasElement,
+    view: Rect,
+    ev: &web_sys::MouseEvent,
+) -> (usize, usize) {
+    let client_width = f64::from(canvas.client_width().max(1));
+    let client_height = f64::from(canvas.client_height().max(1));
+    let x = (f64::from(ev.offset_x()) / client_width * view.width as f64).max(0.0) as usize;
+    let y = (f64::from(ev.offset_y()) / client_height * view.height as f64).max(0.0) as usize;
+    (
+        view.x + x.min(view.width - 1),
+        view.y + y.min(view.height - 1),
+    )
+}
+
+/// Lets the user assign the pixels of `image` to every sticker and white balance of the puzzle, directly in the browser
+///
+/// Dragging on the image flood fills from where the drag started; the length and direction of the drag control the flood fill tolerance. For stickers that flood fill handles badly, the polygon, rectangle, brush and eraser tools (T cycles through them) select pixels by hand, and can be combined with the flood fill (M). Press Enter or click the first vertex to finish a polygon. Press N (or "Assign") to assign the sampled pixels to the current sticker, B (or "Back") to go to the previous sticker, X (or "Clear") to unassign the current sticker, O (or "Overview") to show every assigned sticker in the color of its face, and C (or "Crop") to select a region to zoom into. The mouse wheel zooms in and out about the pointer, and dragging with the middle or right button (or with shift held) pans the zoomed view. Any sticker can be jumped to and reassigned, which is how an `initial` assignment is edited.
+///
+/// `on_finish` receives the assignment along with how much every pixel should count during inference, which is lower for pixels close to the border of their sticker.
+#[component]
+pub fn PixelAssignmentEditor(
+    image: AssignmentImage,
+    puzzle: Arc<PuzzleGeometry>,
+    #[prop(optional_no_strip)] initial: Option<Box<[Pixel]>>,
+    #[prop(into)] on_finish: Callback<(Box<[Pixel]>, Box<[f64]>)>,
+    #[prop(into)] on_cancel: Callback<()>,
+) -> impl IntoView {
+    let container_ref = NodeRef::<html::Div>::new();
+    let canvas_ref = NodeRef::<html::Canvas>::new();
+    let state = RwSignal::new(EditorState::new(
+        image,
+        assignment_targets(&puzzle),
+        initial,
+    ));
+
+    Effect::new(move |_| {
+        if let Some(container) = container_ref.get() {
+            let _ = container.focus();
+        }
+    });
+
+    Effect::new(move |_| {
+        let Some(canvas) = canvas_ref.get() else {
+            return;
+        };
+        state.with(|state| state.draw(&canvas));
+    });
+
+    let finish = move || {
+        info!("Finished pixel assignment");
+        on_finish.run(state.with_untracked(|state| {
+            (state.pixel_assignment.clone(), state.pixel_weights.clone())
+        }));
+    };
+    let assign = move || {
+        if state.try_update(EditorState::assign).unwrap_or(false) {
+            finish();
+        }
+    };
+    let back = move || state.update(EditorState::back);
+    let clear = move || state.update(EditorState::clear);
+    let toggle_overview = move || state.update(EditorState::toggle_overview);
+    let toggle_crop = move || state.update(EditorState::toggle_crop);
+    let next_tool = move || state.update(|state| state.set_tool(state.tool.next()));
+    let toggle_combine_flood_fill = move || state.update(EditorState::toggle_combine_flood_fill);
+    let close_polygon = move || state.update(EditorState::close_polygon);
+
+    let pointer_xy = move |ev: &web_sys::MouseEvent| {
+        let canvas = canvas_ref.get_untracked()?;
+        Some(pointer_to_image(
+            &canvas,
+            state.with_untracked(EditorState::view_rect),
+            ev,
+        ))
+    };
+
+    view! {
+      <div
+        node_ref=container_ref
+        class="flex fixed inset-0 z-50 flex-col gap-2 p-4 bg-black/90 focus:outline-none"
+        tabindex="0"
+        on:keydown=move |ev| match ev.key().as_str() {
+          "n" | "N" => assign(),
+          "b" | "B" => back(),
+          "x" | "X" => clear(),
+          "o" | "O" => toggle_overview(),
+          "c" | "C" => toggle_crop(),
+          "t" | "T" => next_tool(),
+          "m" | "M" => toggle_combine_flood_fill(),
+          "Enter" => close_polygon(),
+          "Escape" => on_cancel.run(()),
+          _ => {}
+        }
+      >
+        <div class="flex gap-4 justify-center items-center">
+          <div class="text-2xl">
+            {move || {
+              state
+                .with(|state| {
+                  let label = state
+                    .targets
+                    .get(state.assigning_idx)
+                    .map(|target| target.label.clone())
+                    .unwrap_or_default();
+                  format!("{label} ({}/{})", state.assigning_idx + 1, state.targets.len())
+                })
+            }}
+          </div>
+          <label>
+            "Jump to "
+            <select
+              class="text-white bg-black border-2 border-white"
+              prop:value=move || state.with(|state| state.assigning_idx.to_string())
+              on:change:target=move |ev| {
+                if let Ok(idx) = ev.target().value().parse() {
+                  state.update(|state| state.jump_to(idx));
+                }
+              }
+            >
+              {state
+                .with_untracked(|state| {
+                  state
+                    .targets
+                    .iter()
+                    .enumerate()
+                    .map(|(i, target)| {
+                      view! { <option value=i.to_string()>{format!("{}. {}", i + 1, target.label)}</option> }
+                    })
+                    .collect_view()
+                })}
+            </select>
+          </label>
+        </div>
+        <div class="flex-1 min-h-0">
+          <canvas
+            node_ref=canvas_ref
+            class="block mx-auto cursor-crosshair touch-none [image-rendering:pixelated]"
+            on:contextmenu=move |ev| ev.prevent_default()
+            on:wheel=move |ev| {
+              ev.prevent_default();
+              if let Some(xy) = pointer_xy(&ev) {
+                // Scroll deltas are usually in steps of 100 pixels per notch of the wheel
+                let factor = ZOOM_STEP.powf(-ev.delta_y() / 100.0);
+                state.update(|state| state.zoom(xy, factor));
+              }
+            }
+            on:pointerdown=move |ev| {
+              if let Some(xy) = pointer_xy(&ev) {
+                if let Some(canvas) = canvas_ref.get_untracked() {
+                  let _ = canvas.set_pointer_capture(ev.pointer_id());
+                }
+                if ev.button() != 0 || ev.shift_key() {
+                  state.update(|state| state.pan_anchor = Some(xy));
+                } else {
+                  state.update(|state| state.pointer_down(xy));
+                }
+              }
+            }
+            on:pointermove=move |ev| {
+              let Some(xy) = pointer_xy(&ev) else {
+                return;
+              };
+              if ev.buttons() == 0 {
+                if state.with_untracked(|state| state.tool != SelectionTool::FloodFill) {
+                  state.update(|state| state.pointer_hover(xy));
+                }
+              } else if state.with_untracked(|state| state.pan_anchor.is_some()) {
+                state.update(|state| state.pan_to(xy));
+              } else {
+                state.update(|state| state.pointer_move(xy));
+              }
+            }
+            on:pointerup=move |_| state.update(EditorState::pointer_up)
+          />
+        </div>
+        <div class="flex gap-4 justify-center">
+          <label>
+            "Tool "
+            <select
+              class="text-white bg-black border-2 border-white"
+              prop:value=move || state.with(|state| state.tool.index().to_string())
+              on:change:target=move |ev| {
+                if let Some(&tool) = ev
+                  .target()
+                  .value()
+                  .parse::<usize>()
+                  .ok()
+                  .and_then(|idx| SelectionTool::ALL.get(idx))
+                {
+                  state.update(|state| state.set_tool(tool));
+                }
+              }
+            >
+              {SelectionTool::ALL
+                .iter()
+                .map(|tool| {
+                  view! { <option value=tool.index().to_string()>{tool.label()}</option> }
+                })
+                .collect_view()}
+            </select>
+          </label>
+          <label>
+            <input
+              type="checkbox"
+              prop:checked=move || state.with(|state| state.combine_flood_fill)
+              on:change=move |_| toggle_combine_flood_fill()
+            />
+            " Combine with flood fill"
+          </label>
+          <label>
+            "Brush size "
+            <input
+              type="range"
+              min=BRUSH_SIZE_MINDEFMAX[0].to_string()
+              max=BRUSH_SIZE_MINDEFMAX[2].to_string()
+              prop:value=move || state.with(|state| state.brush_size.to_string())
+              on:input:target=move |ev| {
+                if let Ok(brush_size) = ev.target().value().parse() {
+                  state.update(|state| state.brush_size = brush_size);
+                }
+              }
+            />
+          </label>
+          <label>
+            "Erosion size "
+            <input
+              type="range"
+              min=EROSION_SIZE_MINDEFMAX[0].to_string()
+              max=EROSION_SIZE_MINDEFMAX[2].to_string()
+              prop:value=move || state.with(|state| state.erosion_size.to_string())
+              on:input:target=move |ev| {
+                if let Ok(erosion_size) = ev.target().value().parse() {
+                  state
+                    .update(|state| {
+                      state.erosion_size = erosion_size;
+                      state.update_selection();
+                    });
+                }
+              }
+            />
+          </label>
+          <label>
+            "Upper diff "
+            <input
+              type="range"
+              min=UPPER_DIFF_MINDEFMAX[0].to_string()
+              max=UPPER_DIFF_MINDEFMAX[2].to_string()
+              prop:value=move || state.with(|state| state.upper_flood_fill_diff.to_string())
+              on:input:target=move |ev| {
+                if let Ok(upper_flood_fill_diff) = ev.target().value().parse() {
+                  state
+                    .update(|state| {
+                      state.upper_flood_fill_diff = upper_flood_fill_diff;
+                      state.update_selection();
+                    });
+                }
+              }
+            />
+          </label>
+        </div>
+        <div class="flex gap-4 justify-center">
+          <label>
+            "Samples "
+            <input
+              type="range"
+              min=NUM_SAMPLES_MINDEFMAX[0].to_string()
+              max=NUM_SAMPLES_MINDEFMAX[2].to_string()
+              prop:value=move || state.with(|state| state.sampling.num_samples.to_string())
+              on:input:target=move |ev| {
+                if let Ok(num_samples) = ev.target().value().parse() {
+                  state.update(|state| state.set_sampling(|sampling| sampling.num_samples = num_samples));
+                }
+              }
+            />
+          </label>
+          <label>
+            "Sampling "
+            <select
+              class="text-white bg-black border-2 border-white"
+              prop:value=move || state.with(|state| state.sampling.strategy.index().to_string())
+              on:change:target=move |ev| {
+                if let Some(&strategy) = ev
+                  .target()
+                  .value()
+                  .parse::<usize>()
+                  .ok()
+                  .and_then(|idx| SamplingStrategy::ALL.get(idx))
+                {
+                  state.update(|state| state.set_sampling(|sampling| sampling.strategy = strategy));
+                }
+              }
+            >
+              {SamplingStrategy::ALL
+                .iter()
+                .map(|strategy| {
+                  view! { <option value=strategy.index().to_string()>{strategy.label()}</option> }
+                })
+                .collect_view()}
+            </select>
+          </label>
+          <label>
+            "Erode until % "
+            <input
+              type="range"
+              min=ERODE_UNTIL_PERCENT_MINDEFMAX[0].to_string()
+              max=ERODE_UNTIL_PERCENT_MINDEFMAX[2].to_string()
+              prop:value=move || state.with(|state| state.sampling.erode_until_percent().to_string())
+              on:input:target=move |ev| {
+                if let Ok(percent) = ev.target().value().parse() {
+                  state.update(|state| state.set_sampling(|sampling| sampling.set_erode_until_percent(percent)));
+                }
+              }
+            />
+          </label>
+          <label>
+            "Min samples "
+            <input
+              type="range"
+              min=MIN_SAMPLES_MINDEFMAX[0].to_string()
+              max=MIN_SAMPLES_MINDEFMAX[2].to_string()
+              prop:value=move || state.with(|state| state.sampling.min_samples.to_string())
+              on:input:target=move |ev| {
+                if let Ok(min_samples) = ev.target().value().parse() {
+                  state.update(|state| state.set_sampling(|sampling| sampling.min_samples = min_samples));
+                }
+              }
+            />
+          </label>
+          <div>{move || state.with(|state| inference_cost_text(&state.targets, &state.sampling))}</div>
+        </div>
+        <div class="flex h-12">
+          <button class="flex-1 border-2 border-white cursor-pointer" on:click=move |_| back()>
+            "Back"
+          </button>
+          <button class="flex-1 border-2 border-white cursor-pointer" on:click=move |_| clear()>
+            "Clear sticker"
+          </button>
+          <button class="flex-1 border-2 border-white cursor-pointer" on:click=move |_| toggle_overview()>
+            {move || if state.with(|state| state.overview) { "Hide overview" } else { "Overview" }}
+          </button>
+          <button class="flex-1 border-2 border-white cursor-pointer" on:click=move |_| toggle_crop()>
+            {move || {
+              state
+                .with(|state| match state.crop {
+                  CropState::NoCrop => "Crop",
+                  CropState::SelectingCrop(_) => "Cancel crop",
+                  CropState::Crop(_) => "Uncrop",
+                })
+            }}
+          </button>
+          <button class="flex-1 border-2 border-white cursor-pointer" on:click=move |_| assign()>
+            "Assign sticker"
+          </button>
+          <button class="flex-1 border-2 border-white cursor-pointer" on:click=move |_| finish()>
+            "Done"
+          </button>
+          <button class="flex-1 border-2 border-white cursor-pointer" on:click=move |_| on_cancel.run(())>
+            "Cancel"
+          </button>
+        </div>
+      </div>
+    }
+}
@@ -0,0 +1,397 @@
+//! Named profiles of processors, one for every rig and lighting setup, kept in the model directory
+
+use leptos::serde_json;
+use log::warn;
+use qvis::CVProcessor;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    app::{ModelKind, PUZZLE_NAME, ProfileInfo},
+    storage,
+    video::{CameraSettings, DEFAULT_WIDTH},
+};
+
+/// The file in the model directory holding the registry
+const REGISTRY_FILE: &str = "registry.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RegistryFile {
+    /// The profile that the server's processor was selected from, which it is saved back to at every checkpoint
+    active: Option<String>,
+    profiles: BTreeMap<String, ProfileInfo>,
+}
+
+/// The model registry, shared between server functions and robot commands
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    /// The profiles, only locked for as long as it takes to read or change them, so that listing profiles never waits on the disk
+    state: Arc<Mutex<RegistryFile>>,
+    /// Held by every change while it reads and writes files, so that changes are saved in the order that they were made. Only locked on blocking threads.
+    changes: Arc<Mutex<()>>,
+}
+
+fn not_found(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("There is no profile named {name}"),
+    )
+}
+
+fn already_exists(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("There already is a profile named {name}"),
+    )
+}
+
+fn profile_info(
+    name: &str,
+    cv_processor: &CVProcessor,
+    created: u64,
+    tags: Vec<String>,
+    camera: CameraSettings,
+) -> ProfileInfo {
+    let width = DEFAULT_WIDTH as usize;
+    ProfileInfo {
+        name: name.to_owned(),
+        puzzle: PUZZLE_NAME.to_owned(),
+        resolution: cv_processor
+            .resolution()
+            .unwrap_or((width, cv_processor.image_size() / width)),
+        created,
+        calibrations: cv_processor.calibrations(),
+        accuracy: cv_processor.accuracy(),
+        tags,
+        camera,
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs())
+}
+
+impl Registry {
+    /// Loads the registry, adding a profile for every processor in the model directory that isn't in it yet, like processors that were saved before there was a registry
+    pub fn load() -> Registry {
+        let path = storage::model_dir().join(REGISTRY_FILE);
+        let mut file = match std::fs::read(&path) {
+            Ok(json) => serde_json::from_slice(&json).unwrap_or_else(|e| {
+                warn!("Failed to load {}, starting over: {e}", path.display());
+                RegistryFile::default()
+            }),
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    warn!("Failed to read {}, starting over: {e}", path.display());
+                }
+                RegistryFile::default()
+            }
+        };
+
+        // Without knowing which processors exist, there's no telling which profiles are stale, so keep all of them
+        let names = match storage::list_models(ModelKind::CVProcessor) {
+            Ok(names) => Some(names),
+            Err(e) => {
+                warn!("Failed to list saved CVProcessors, keeping every profile: {e}");
+                None
+            }
+        };
+        let mut changed = false;
+        for name in names.iter().flatten() {
+            if file.profiles.contains_key(name) {
+                continue;
+            }
+            match storage::load_cv_processor(name) {
+                Ok(cv_processor) => {
+                    file.profiles.insert(
+                        name.clone(),
+                        profile_info(
+                            name,
+                            &cv_processor,
+                            now(),
+                            Vec::new(),
+                            CameraSettings::default(),
+                        ),
+                    );
+                    changed = true;
+                }
+                // The processor is still there, so it gets a profile once it loads again
+                Err(e) => warn!("Failed to load CVProcessor {name}, skipping it: {e}"),
+            }
+        }
+        // Forget profiles whose processor was deleted behind our back
+        if let Some(names) = &names {
+            let before = file.profiles.len();
+            file.profiles.retain(|name, _| names.contains(name));
+            changed |= file.profiles.len() != before;
+        }
+        if file
+            .active
+            .as_ref()
+            .is_some_and(|active| !file.profiles.contains_key(active))
+        {
+            file.active = None;
+            changed = true;
+        }
+
+        if changed && let Err(e) = persist(&file) {
+            warn!("Failed to save {}: {e}", path.display());
+        }
+        Registry {
+            state: Arc::new(Mutex::new(file)),
+            changes: Arc::default(),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, RegistryFile> {
+        self.state.lock().unwrap()
+    }
+
+    fn begin_change(&self) -> MutexGuard<'_, ()> {
+        self.changes.lock().unwrap()
+    }
+
+    /// Applies `change` to the profiles and saves the registry, without holding the state lock while writing
+    fn commit<T>(
+        &self,
+        _changes: &MutexGuard<'_, ()>,
+        change: impl FnOnce(&mut RegistryFile) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let (value, json) = {
+            let mut file = self.state();
+            let value = change(&mut file)?;
+            (value, serde_json::to_vec_pretty(&*file)?)
+        };
+        write_registry(&json)?;
+        Ok(value)
+    }
+
+    /// Returns the name of the active profile and every profile, in alphabetical order
+    pub fn list(&self) -> (Option<String>, Vec<ProfileInfo>) {
+        let file = self.state();
+        (
+            file.active.clone(),
+            file.profiles.values().cloned().collect(),
+        )
+    }
+
+    /// Returns the name and camera settings of the active profile, if there is one
+    pub fn active_camera(&self) -> Option<(String, CameraSettings)> {
+        let file = self.state();
+        let active = file.active.clone()?;
+        let camera = file.profiles.get(&active)?.camera.clone();
+        Some((active, camera))
+    }
+
+    /// Saves `cv_processor` as the profile with the given name, replacing the processor of any profile with that name but keeping its creation date, tags and camera settings
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `name` isn't a valid name or if the processor or registry can't be written.
+    pub fn save(&self, name: &str, cv_processor: &CVProcessor) -> io::Result<ProfileInfo> {
+        let changes = self.begin_change();
+        self.save_locked(&changes, name, cv_processor)
+    }
+
+    fn save_locked(
+        &self,
+        changes: &MutexGuard<'_, ()>,
+        name: &str,
+        cv_processor: &CVProcessor,
+    ) -> io::Result<ProfileInfo> {
+        storage::save_cv_processor(name, cv_processor)?;
+        self.commit(changes, |file| {
+            let (created, tags, camera) = file.profiles.get(name).map_or_else(
+                || (now(), Vec::new(), CameraSettings::default()),
+                |old| (old.created, old.tags.clone(), old.camera.clone()),
+            );
+            let info = profile_info(name, cv_processor, created, tags, camera);
+            file.profiles.insert(name.to_owned(), info.clone());
+            Ok(info)
+        })
+    }
+
+    /// Saves `cv_processor` to the active profile, if there is one
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the processor or registry can't be written.
+    pub fn save_active(&self, cv_processor: &CVProcessor) -> io::Result<()> {
+        let changes = self.begin_change();
+        let active = self.state().active.clone();
+        if let Some(active) = active {
+            self.save_locked(&changes, &active, cv_processor)?;
+        }
+        Ok(())
+    }
+
+    /// Makes the profile with the given name the active one and returns its processor, which the caller should make the server's processor
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if there is no such profile or if its processor can't be read.
+    pub fn select(&self, name: &str) -> io::Result<CVProcessor> {
+        let changes = self.begin_change();
+        if !self.state().profiles.contains_key(name) {
+            return Err(not_found(name));
+        }
+        let cv_processor = storage::load_cv_processor(name)?;
+        self.commit(&changes, |file| {
+            file.active = Some(name.to_owned());
+            Ok(())
+        })?;
+        Ok(cv_processor)
+    }
+
+    /// Makes the profile with the given name the active one without loading it, for when the server's processor was just saved to it
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if there is no such profile or if the registry can't be written.
+    pub fn activate(&self, name: &str) -> io::Result<()> {
+        let changes = self.begin_change();
+        self.commit(&changes, |file| {
+            if !file.profiles.contains_key(name) {
+                return Err(not_found(name));
+            }
+            file.active = Some(name.to_owned());
+            Ok(())
+        })
+    }
+
+    /// Copies the profile `name` to a new profile `new_name`, which gets its own creation date
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if there is no profile `name`, if there already is a profile `new_name`, if `new_name` isn't a valid name, or if the files can't be copied.
+    pub fn duplicate(&self, name: &str, new_name: &str) -> io::Result<ProfileInfo> {
+        let changes = self.begin_change();
+        let info = {
+            let file = self.state();
+            let Some(old) = file.profiles.get(name) else {
+                return Err(not_found(name));
+            };
+            if file.profiles.contains_key(new_name) {
+                return Err(already_exists(new_name));
+            }
+            ProfileInfo {
+                name: new_name.to_owned(),
+                created: now(),
+                ..old.clone()
+            }
+        };
+        std::fs::copy(
+            storage::model_path(ModelKind::CVProcessor, name)?.with_extension("json"),
+            storage::model_path(ModelKind::CVProcessor, new_name)?.with_extension("json"),
+        )?;
+        self.commit(&changes, |file| {
+            file.profiles.insert(new_name.to_owned(), info.clone());
+            Ok(info)
+        })
+    }
+
+    /// Renames the profile `name` to `new_name`, which stays active if it was
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if there is no profile `name`, if there already is a profile `new_name`, if `new_name` isn't a valid name, or if the file can't be renamed.
+    pub fn rename(&self, name: &str, new_name: &str) -> io::Result<()> {
+        let changes = self.begin_change();
+        {
+            let file = self.state();
+            if !file.profiles.contains_key(name) {
+                return Err(not_found(name));
+            }
+            if file.profiles.contains_key(new_name) {
+                return Err(already_exists(new_name));
+            }
+        }
+        std::fs::rename(
+            storage::model_path(ModelKind::CVProcessor, name)?.with_extension("json"),
+            storage::model_path(ModelKind::CVProcessor, new_name)?.with_extension("json"),
+        )?;
+        self.commit(&changes, |file| {
+            // Only changes take the profile away, and they wait for this one
+            let Some(mut info) = file.profiles.remove(name) else {
+                return Err(not_found(name));
+            };
+            info.name = new_name.to_owned();
+            file.profiles.insert(new_name.to_owned(), info);
+            if file.active.as_deref() == Some(name) {
+                file.active = Some(new_name.to_owned());
+            }
+            Ok(())
+        })
+    }
+
+    /// Deletes the profile with the given name. The server keeps its processor if it was the active profile, but stops saving it anywhere but its checkpoints.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if there is no such profile or if its processor can't be deleted.
+    pub fn delete(&self, name: &str) -> io::Result<()> {
+        let changes = self.begin_change();
+        if !self.state().profiles.contains_key(name) {
+            return Err(not_found(name));
+        }
+        storage::delete_model(ModelKind::CVProcessor, name)?;
+        self.commit(&changes, |file| {
+            file.profiles.remove(name);
+            if file.active.as_deref() == Some(name) {
+                file.active = None;
+            }
+            Ok(())
+        })
+    }
+
+    /// Replaces the tags of the profile with the given name, ignoring blank tags
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if there is no such profile or if the registry can't be written.
+    pub fn set_tags(&self, name: &str, tags: Vec<String>) -> io::Result<()> {
+        let changes = self.begin_change();
+        self.commit(&changes, |file| {
+            let Some(info) = file.profiles.get_mut(name) else {
+                return Err(not_found(name));
+            };
+            info.tags = tags
+                .into_iter()
+                .map(|tag| tag.trim().to_owned())
+                .filter(|tag| !tag.is_empty())
+                .collect();
+            Ok(())
+        })
+    }
+
+    /// Replaces the camera settings of the profile with the given name
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if there is no such profile or if the registry can't be written.
+    pub fn set_camera(&self, name: &str, camera: CameraSettings) -> io::Result<()> {
+        let changes = self.begin_change();
+        self.commit(&changes, |file| {
+            let Some(info) = file.profiles.get_mut(name) else {
+                return Err(not_found(name));
+            };
+            info.camera = camera;
+            Ok(())
+        })
+    }
+}
+
+fn persist(file: &RegistryFile) -> io::Result<()> {
+    write_registry(&serde_json::to_vec_pretty(file)?)
+}
+
+fn write_registry(json: &[u8]) -> io::Result<()> {
+    storage::write_atomically(&storage::model_dir().join(REGISTRY_FILE), json)
+}
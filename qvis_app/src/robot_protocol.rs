@@ -0,0 +1,502 @@
+//! The protocol that robot controllers use to drive the vision server, over stdin or TCP.
+//!
+//! Every request is one line. Lines starting with `{` are JSON requests like
+//! `{"version":1,"id":7,"command":"take_picture"}` or
+//! `{"version":1,"id":8,"command":"calibrate","permutation":"..."}` or
+//! `{"version":1,"id":9,"command":"select_profile","name":"..."}`, and are answered with one JSON line like
+//! `{"version":1,"id":7,"status":"ok","payload":{"kind":"recognized","permutation":"...","confidence":0.93,"waited_ms":120,"occluded":[]}}` or
+//! `{"version":1,"id":8,"status":"error","error":{"code":"invalid_permutation","message":"..."}}`.
+//!
+//! Any other line is a legacy text command, `TAKE_PICTURE`, `CALIBRATE {permutation}` or `PROFILE {name}`, which is answered with
+//! `DONE {permutation};{confidence percent}`, `DONE ` or `DONE {error message}`.
+
+use leptos::serde_json;
+use puzzle_theory::permutations::Permutation;
+use serde::{Deserialize, Serialize};
+
+/// The version of the JSON protocol, which requests have to match
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// What a robot controller asked for
+#[derive(Debug, Clone)]
+pub enum RobotCommand {
+    /// Take a picture and recognize the state of the puzzle in it
+    TakePicture,
+    /// Take a picture and calibrate with the puzzle being in the given state
+    Calibrate(Permutation),
+    /// Switch to the profile with the given name, see [`crate::registry`]
+    SelectProfile(String),
+}
+
+/// Which protocol a request came in with, and therefore how to answer it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    Text,
+    Json { id: Option<u64> },
+}
+
+/// The result of a successful request
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RobotPayload {
+    Recognized {
+        #[serde(serialize_with = "serialize_display")]
+        permutation: Permutation,
+        /// Between zero and one
+        confidence: f64,
+        /// How long the picture waited for the puzzle to hold still, in milliseconds
+        waited_ms: u64,
+        /// The stickers that something covered, which the permutation was recognized without
+        occluded: Vec<usize>,
+    },
+    Calibrated {
+        /// How long the picture waited for the puzzle to hold still, in milliseconds
+        waited_ms: u64,
+    },
+    ProfileSelected {
+        name: String,
+    },
+}
+
+/// A machine-readable reason for a request failing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RobotErrorCode {
+    /// The request isn't valid JSON or is missing fields
+    MalformedRequest,
+    /// The request is for a different version of the protocol
+    UnsupportedVersion,
+    /// The command isn't known
+    UnknownCommand,
+    /// The permutation of a calibrate request couldn't be parsed
+    InvalidPermutation,
+    /// The browser answered with something that doesn't match the request
+    UnexpectedReply,
+    /// No browser was connected to take the picture with
+    NoClient,
+    /// The browser kept disconnecting or reloading before it could reply
+    ClientLost,
+    /// The browser took too long to reply
+    TimedOut,
+    /// The server has no processor to recognize pictures with, because pixels haven't been assigned yet
+    NoProcessor,
+    /// The camera failed to take a picture, or the picture doesn't fit the processor
+    CaptureFailed,
+    /// There is no profile with the given name
+    UnknownProfile,
+    /// Something went wrong on the server while handling the request
+    ServerError,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RobotError {
+    pub code: RobotErrorCode,
+    pub message: String,
+}
+
+impl RobotError {
+    pub fn new(code: RobotErrorCode, message: impl Into<String>) -> RobotError {
+        RobotError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum RobotStatus {
+    Ok,
+    Error,
+}
+
+#[derive(Debug, Serialize)]
+struct RobotResponse<'a> {
+    version: u32,
+    id: Option<u64>,
+    status: RobotStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload: Option<&'a RobotPayload>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a RobotError>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum JsonCommand {
+    TakePicture,
+    Calibrate { permutation: String },
+    SelectProfile { name: String },
+}
+
+fn serialize_display<S: serde::Serializer>(
+    value: &impl std::fmt::Display,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+fn profile_name(name: &str) -> Result<String, RobotError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(RobotError::new(
+            RobotErrorCode::MalformedRequest,
+            "Missing profile name",
+        ));
+    }
+    Ok(name.to_owned())
+}
+
+fn parse_permutation(perm_str: &str) -> Result<Permutation, RobotError> {
+    perm_str.trim().parse::<Permutation>().map_err(|_| {
+        RobotError::new(
+            RobotErrorCode::InvalidPermutation,
+            format!("Invalid permutation string: {}", perm_str.trim()),
+        )
+    })
+}
+
+/// Parses one line from a robot controller. Returns `None` for lines that should be ignored, which are blank lines and unknown text commands.
+pub fn parse_line(line: &str) -> Option<(Dialect, Result<RobotCommand, RobotError>)> {
+    let line = line.trim();
+    if line.starts_with('{') {
+        return Some(parse_json_line(line));
+    }
+
+    let command = if line.starts_with("TAKE_PICTURE") {
+        Ok(RobotCommand::TakePicture)
+    } else if line.starts_with("CALIBRATE") {
+        parse_permutation(line.trim_start_matches("CALIBRATE")).map(RobotCommand::Calibrate)
+    } else if let Some(name) = line.strip_prefix("PROFILE") {
+        profile_name(name).map(RobotCommand::SelectProfile)
+    } else {
+        if !line.is_empty() {
+            leptos::logging::log!("WARNING: Unknown command: {}", line);
+        }
+        return None;
+    };
+    Some((Dialect::Text, command))
+}
+
+fn parse_json_line(line: &str) -> (Dialect, Result<RobotCommand, RobotError>) {
+    let value = match serde_json::from_str::<serde_json::Value>(line) {
+        Ok(value) => value,
+        Err(e) => {
+            return (
+                Dialect::Json { id: None },
+                Err(RobotError::new(
+                    RobotErrorCode::MalformedRequest,
+                    e.to_string(),
+                )),
+            );
+        }
+    };
+    // Answer with the id even if the rest of the request is bad, so the controller knows which request failed
+    let id = value.get("id").and_then(serde_json::Value::as_u64);
+    let dialect = Dialect::Json { id };
+    (dialect, parse_json_request(id, value))
+}
+
+fn parse_json_request(
+    id: Option<u64>,
+    value: serde_json::Value,
+) -> Result<RobotCommand, RobotError> {
+    match value.get("version").and_then(serde_json::Value::as_u64) {
+        Some(version) if version == u64::from(PROTOCOL_VERSION) => {}
+        Some(version) => {
+            return Err(RobotError::new(
+                RobotErrorCode::UnsupportedVersion,
+                format!("Unsupported protocol version {version}, expected {PROTOCOL_VERSION}"),
+            ));
+        }
+        None => {
+            return Err(RobotError::new(
+                RobotErrorCode::MalformedRequest,
+                "Missing or invalid version",
+            ));
+        }
+    }
+    if id.is_none() {
+        return Err(RobotError::new(
+            RobotErrorCode::MalformedRequest,
+            "Missing or invalid id",
+        ));
+    }
+    if let Some(command) = value.get("command").and_then(serde_json::Value::as_str)
+        && !matches!(command, "take_picture" | "calibrate" | "select_profile")
+    {
+        return Err(RobotError::new(
+            RobotErrorCode::UnknownCommand,
+            format!("Unknown command: {command}"),
+        ));
+    }
+
+    match serde_json::from_value::<JsonCommand>(value) {
+        Ok(JsonCommand::TakePicture) => Ok(RobotCommand::TakePicture),
+        Ok(JsonCommand::Calibrate { permutation }) => {
+            parse_permutation(&permutation).map(RobotCommand::Calibrate)
+        }
+        Ok(JsonCommand::SelectProfile { name }) => {
+            profile_name(&name).map(RobotCommand::SelectProfile)
+        }
+        Err(e) => Err(RobotError::new(
+            RobotErrorCode::MalformedRequest,
+            e.to_string(),
+        )),
+    }
+}
+
+/// Formats the answer to a request, including the trailing newline
+pub fn format_reply(dialect: Dialect, outcome: &Result<RobotPayload, RobotError>) -> String {
+    match dialect {
+        Dialect::Text => {
+            let done_string = match outcome {
+                Ok(RobotPayload::Recognized {
+                    permutation,
+                    confidence,
+                    ..
+                }) => format!("{permutation};{:.2}", confidence * 100.),
+                Ok(RobotPayload::Calibrated { .. } | RobotPayload::ProfileSelected { .. }) => {
+                    String::new()
+                }
+                // Every reply is one line
+                Err(e) => e.message.replace(['\r', '\n'], " "),
+            };
+            format!("DONE {done_string}\n")
+        }
+        Dialect::Json { id } => {
+            let response = RobotResponse {
+                version: PROTOCOL_VERSION,
+                id,
+                status: if outcome.is_ok() {
+                    RobotStatus::Ok
+                } else {
+                    RobotStatus::Error
+                },
+                payload: outcome.as_ref().ok(),
+                error: outcome.as_ref().err(),
+            };
+            #[allow(clippy::missing_panics_doc)]
+            let mut line = serde_json::to_string(&response)
+                .expect("robot responses only contain strings and numbers");
+            line.push('\n');
+            line
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use leptos::serde_json::{self, json};
+    use puzzle_theory::permutations::Permutation;
+
+    use super::{
+        Dialect, RobotCommand, RobotError, RobotErrorCode, RobotPayload, format_reply, parse_line,
+    };
+
+    fn error_code(line: &str) -> (Dialect, RobotErrorCode) {
+        match parse_line(line) {
+            Some((dialect, Err(e))) => (dialect, e.code),
+            other => panic!("Expected an error for {line:?} but got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_text_commands() {
+        let perm = Permutation::from_cycles(vec![vec![1, 2, 3]]);
+
+        assert!(matches!(
+            parse_line("TAKE_PICTURE\n"),
+            Some((Dialect::Text, Ok(RobotCommand::TakePicture)))
+        ));
+        match parse_line(&format!("CALIBRATE {perm}")) {
+            Some((Dialect::Text, Ok(RobotCommand::Calibrate(parsed)))) => assert_eq!(parsed, perm),
+            other => panic!("{other:?}"),
+        }
+        assert!(matches!(
+            parse_line("PROFILE  rig two "),
+            Some((Dialect::Text, Ok(RobotCommand::SelectProfile(name)))) if name == "rig two"
+        ));
+        assert_eq!(
+            error_code("CALIBRATE banana"),
+            (Dialect::Text, RobotErrorCode::InvalidPermutation)
+        );
+        assert_eq!(
+            error_code("PROFILE "),
+            (Dialect::Text, RobotErrorCode::MalformedRequest)
+        );
+
+        assert!(parse_line("").is_none());
+        assert!(parse_line("   ").is_none());
+        assert!(parse_line("DANCE").is_none());
+    }
+
+    #[test]
+    fn test_json_commands() {
+        let perm = Permutation::from_cycles(vec![vec![1, 2, 3]]);
+
+        assert!(matches!(
+            parse_line(r#"{"version":1,"id":7,"command":"take_picture"}"#),
+            Some((Dialect::Json { id: Some(7) }, Ok(RobotCommand::TakePicture)))
+        ));
+        let calibrate =
+            json!({"version": 1, "id": 8, "command": "calibrate", "permutation": perm.to_string()});
+        match parse_line(&calibrate.to_string()) {
+            Some((Dialect::Json { id: Some(8) }, Ok(RobotCommand::Calibrate(parsed)))) => {
+                assert_eq!(parsed, perm);
+            }
+            other => panic!("{other:?}"),
+        }
+        assert!(matches!(
+            parse_line(r#"{"version":1,"id":9,"command":"select_profile","name":"rig"}"#),
+            Some((Dialect::Json { id: Some(9) }, Ok(RobotCommand::SelectProfile(name)))) if name == "rig"
+        ));
+    }
+
+    #[test]
+    fn test_json_errors() {
+        assert_eq!(
+            error_code(r#"{"version":1,"command":"take_picture"}"#),
+            (Dialect::Json { id: None }, RobotErrorCode::MalformedRequest)
+        );
+        assert_eq!(
+            error_code(r#"{"version":2,"id":3,"command":"take_picture"}"#),
+            (
+                Dialect::Json { id: Some(3) },
+                RobotErrorCode::UnsupportedVersion
+            )
+        );
+        assert_eq!(
+            error_code(r#"{"id":3,"command":"take_picture"}"#),
+            (
+                Dialect::Json { id: Some(3) },
+                RobotErrorCode::MalformedRequest
+            )
+        );
+        assert_eq!(
+            error_code(r#"{"version":1,"id":4,"command":"dance"}"#),
+            (
+                Dialect::Json { id: Some(4) },
+                RobotErrorCode::UnknownCommand
+            )
+        );
+        assert_eq!(
+            error_code(r#"{"version":1,"id":5,"command":"calibrate","permutation":"banana"}"#),
+            (
+                Dialect::Json { id: Some(5) },
+                RobotErrorCode::InvalidPermutation
+            )
+        );
+        assert_eq!(
+            error_code(r#"{"version":1,"id":6,"command":"calibrate"}"#),
+            (
+                Dialect::Json { id: Some(6) },
+                RobotErrorCode::MalformedRequest
+            )
+        );
+        assert_eq!(
+            error_code(r#"{"version":1,"id":7,"command":"select_profile","name":" "}"#),
+            (
+                Dialect::Json { id: Some(7) },
+                RobotErrorCode::MalformedRequest
+            )
+        );
+        assert_eq!(
+            error_code("{not json"),
+            (Dialect::Json { id: None }, RobotErrorCode::MalformedRequest)
+        );
+    }
+
+    #[test]
+    fn test_text_replies() {
+        let perm = Permutation::from_cycles(vec![vec![1, 2, 3]]);
+        let recognized = Ok(RobotPayload::Recognized {
+            permutation: perm.clone(),
+            confidence: 0.93,
+            waited_ms: 120,
+            occluded: vec![4],
+        });
+
+        assert_eq!(
+            format_reply(Dialect::Text, &recognized),
+            format!("DONE {perm};93.00\n")
+        );
+        assert_eq!(
+            format_reply(
+                Dialect::Text,
+                &Ok(RobotPayload::Calibrated { waited_ms: 0 })
+            ),
+            "DONE \n"
+        );
+        assert_eq!(
+            format_reply(
+                Dialect::Text,
+                &Ok(RobotPayload::ProfileSelected {
+                    name: "rig".to_owned()
+                })
+            ),
+            "DONE \n"
+        );
+        assert_eq!(
+            format_reply(
+                Dialect::Text,
+                &Err(RobotError::new(RobotErrorCode::NoClient, "No client"))
+            ),
+            "DONE No client\n"
+        );
+        assert_eq!(
+            format_reply(
+                Dialect::Text,
+                &Err(RobotError::new(
+                    RobotErrorCode::CaptureFailed,
+                    "No camera\r\nat all"
+                ))
+            ),
+            "DONE No camera  at all\n"
+        );
+    }
+
+    #[test]
+    fn test_json_replies() {
+        let perm = Permutation::from_cycles(vec![vec![1, 2, 3]]);
+        let recognized = Ok(RobotPayload::Recognized {
+            permutation: perm.clone(),
+            confidence: 0.93,
+            waited_ms: 120,
+            occluded: vec![4],
+        });
+
+        let reply = format_reply(Dialect::Json { id: Some(7) }, &recognized);
+        assert!(reply.ends_with('\n'));
+        assert_eq!(reply.matches('\n').count(), 1);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&reply).unwrap(),
+            json!({
+                "version": 1,
+                "id": 7,
+                "status": "ok",
+                "payload": {
+                    "kind": "recognized",
+                    "permutation": perm.to_string(),
+                    "confidence": 0.93,
+                    "waited_ms": 120,
+                    "occluded": [4],
+                },
+            })
+        );
+
+        let reply = format_reply(
+            Dialect::Json { id: None },
+            &Err(RobotError::new(RobotErrorCode::TimedOut, "Too slow")),
+        );
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&reply).unwrap(),
+            json!({
+                "version": 1,
+                "id": null,
+                "status": "error",
+                "error": {"code": "timed_out", "message": "Too slow"},
+            })
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! Recognition on the server, for taking pictures without a browser open or recognizing pictures that a client pushes
+
+use log::{info, warn};
+use opencv::{
+    core::Mat,
+    imgcodecs::{self, IMREAD_COLOR},
+    prelude::*,
+    videoio::{self, VideoCapture},
+};
+use puzzle_theory::permutations::Permutation;
+use qvis::CVProcessor;
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
+
+use crate::{
+    app::{RequestId, occluded_stickers},
+    video::{
+        DEFAULT_WIDTH, STABILITY_FRAME_INTERVAL_MS, STABILITY_TIMEOUT, STABILITY_TOLERANCE,
+        picture_difference,
+    },
+};
+
+/// The environment variable holding the index of the camera that the server takes pictures with. Robot commands are handled without a browser if it is set.
+pub const SERVER_CAMERA_VAR: &str = "QVIS_SERVER_CAMERA";
+/// How many frames to throw away before taking a picture, because cameras buffer frames from before the picture was asked for
+const STALE_FRAMES: usize = 4;
+
+/// The processor that the server recognizes pictures with
+#[derive(Debug, Clone)]
+pub struct ServerProcessor(
+    pub Arc<watch::Sender<Option<CVProcessor>>>,
+    /// The newest robot request that the processor was calibrated for. Requests only get newer, so anything else is an answer to a request that was already calibrated for or given up on.
+    Arc<AtomicU64>,
+);
+
+impl Default for ServerProcessor {
+    fn default() -> Self {
+        ServerProcessor(Arc::new(watch::Sender::new(None)), Arc::default())
+    }
+}
+
+impl ServerProcessor {
+    pub fn set(&self, cv_processor: CVProcessor) {
+        self.0.send_replace(Some(cv_processor));
+    }
+
+    /// Recognizes the state of the puzzle in `picture` along with the stickers that something covered, or returns `None` if there is no processor yet
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `picture` doesn't fit the processor, see [`picture_pixels`].
+    pub fn process(&self, picture: &Mat) -> opencv::Result<Option<(Permutation, f64, Vec<usize>)>> {
+        let cv_processor = self.0.borrow();
+        let Some(cv_processor) = cv_processor.as_ref() else {
+            return Ok(None);
+        };
+        let pixels = picture_pixels(picture, cv_processor)?;
+        let (permutation, confidence, stickers) = cv_processor.recognize_stickers(&pixels);
+        Ok(Some((
+            permutation,
+            confidence,
+            occluded_stickers(&stickers),
+        )))
+    }
+
+    /// Calibrates with the puzzle in `picture` being in the given state and returns whether there was a processor to calibrate. Nothing recognizes the picture first, so it doesn't count toward the processor's accuracy.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `picture` doesn't fit the processor, see [`picture_pixels`].
+    pub fn calibrate(&self, picture: &Mat, permutation: &Permutation) -> opencv::Result<bool> {
+        let pixels = match self.0.borrow().as_ref() {
+            Some(cv_processor) => picture_pixels(picture, cv_processor)?,
+            None => return Ok(false),
+        };
+        Ok(self.calibrate_pixels(&pixels, permutation, None))
+    }
+
+    /// Calibrates with pixels that were already resampled to fit the processor, like the ones that clients take, and returns whether there was a processor that they fit. Calibrating here instead of in the client means that calibrations always apply to the newest processor. `recognized` is what the caller recognized in the pixels, see [`CVProcessor::calibrate`].
+    pub fn calibrate_pixels(
+        &self,
+        pixels: &[(f64, f64, f64)],
+        permutation: &Permutation,
+        recognized: Option<&Permutation>,
+    ) -> bool {
+        self.0.send_if_modified(|maybe_cv_processor| {
+            let Some(cv_processor) = maybe_cv_processor else {
+                return false;
+            };
+            if cv_processor.image_size() != pixels.len() {
+                return false;
+            }
+            cv_processor.calibrate(pixels, permutation, recognized);
+            true
+        })
+    }
+
+    /// Calibrates for the robot request with the given id with the pixels that are assigned to stickers in a picture that a client took, given as their indices and colors, and returns whether there was a processor that they fit. Every other pixel is left out because nothing calibrates with it. Only the first answer to a request is calibrated with, so that a client that seemed gone but answered anyway doesn't calibrate again.
+    pub fn calibrate_assigned_pixels(
+        &self,
+        request: RequestId,
+        assigned: &[(usize, (f64, f64, f64))],
+        permutation: &Permutation,
+        recognized: &Permutation,
+    ) -> bool {
+        let mut fits = false;
+        self.0.send_if_modified(|maybe_cv_processor| {
+            let Some(cv_processor) = maybe_cv_processor else {
+                return false;
+            };
+            let mut pixels = vec![(0.0, 0.0, 0.0); cv_processor.image_size()].into_boxed_slice();
+            let mut given = vec![false; pixels.len()];
+            for &(idx, pixel) in assigned {
+                if idx >= pixels.len() {
+                    return false;
+                }
+                pixels[idx] = pixel;
+                given[idx] = true;
+            }
+            // The client may have had a different pixel assignment
+            fits = cv_processor
+                .pixel_assignment_locations()
+                .iter()
+                .zip(&given)
+                .all(|(&assigned, &given)| given || !assigned);
+            if !fits {
+                return false;
+            }
+            if self.1.fetch_max(request, Ordering::Relaxed) >= request {
+                warn!("Already calibrated for request {request}, skipping another answer to it");
+                return false;
+            }
+            cv_processor.calibrate(&pixels, permutation, Some(recognized));
+            true
+        });
+        fits
+    }
+}
+
+/// A camera attached to the server
+pub struct ServerCamera(Mutex<VideoCapture>);
+
+impl ServerCamera {
+    /// Opens the camera with the given index
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if there is no such camera or if `OpenCV` fails.
+    pub fn open(index: i32) -> opencv::Result<ServerCamera> {
+        let capture = VideoCapture::new(index, videoio::CAP_ANY)?;
+        if !capture.is_opened()? {
+            return Err(opencv::Error::new(
+                opencv::core::StsError,
+                format!("Failed to open camera {index}"),
+            ));
+        }
+        Ok(ServerCamera(Mutex::new(capture)))
+    }
+
+    /// Waits until the pixels that the server's processor assigned to stickers stay the same for `stable_for`, the way the browser does, and takes a BGR picture of the puzzle holding still. Gives up waiting after [`STABILITY_TIMEOUT`]. Doesn't wait if there is no processor, because there are no assigned pixels to watch. Returns the picture and how long it waited.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the camera doesn't give a frame, if the frames don't fit the processor, see [`picture_pixels`], or if `OpenCV` fails.
+    pub fn capture_when_stable(
+        &self,
+        server_processor: &ServerProcessor,
+        stable_for: Duration,
+    ) -> opencv::Result<(Mat, Duration)> {
+        #[allow(clippy::missing_panics_doc)]
+        let mut capture = self.0.lock().unwrap();
+        for _ in 0..STALE_FRAMES {
+            capture.grab()?;
+        }
+        let start = Instant::now();
+        let locations = server_processor
+            .0
+            .borrow()
+            .as_ref()
+            .map(CVProcessor::pixel_assignment_locations);
+        if stable_for.is_zero() || locations.is_none() {
+            return Ok((read_frame(&mut capture)?, Duration::ZERO));
+        }
+
+        let interval = Duration::from_millis(STABILITY_FRAME_INTERVAL_MS.into());
+        let mut previous = None::<(Box<[(f64, f64, f64)]>, Duration)>;
+        let mut stable_since = Duration::ZERO;
+        loop {
+            // Reading every frame keeps the camera from buffering stale ones
+            let frame = read_frame(&mut capture)?;
+            let now = start.elapsed();
+            if previous
+                .as_ref()
+                .is_some_and(|(_, compared_at)| now - *compared_at < interval)
+            {
+                continue;
+            }
+            let pixels = match server_processor.0.borrow().as_ref() {
+                Some(cv_processor) => picture_pixels(&frame, cv_processor)?,
+                None => return Ok((frame, now)),
+            };
+            let moved = previous.as_ref().is_none_or(|(previous, _)| {
+                previous.len() != pixels.len()
+                    || picture_difference(previous, &pixels, locations.as_deref())
+                        > STABILITY_TOLERANCE
+            });
+            if moved {
+                stable_since = now;
+            } else if now - stable_since >= stable_for {
+                info!("The puzzle held still after {} ms", now.as_millis());
+                return Ok((frame, now));
+            }
+            if now >= STABILITY_TIMEOUT {
+                warn!(
+                    "The puzzle didn't hold still for {} ms, taking the picture anyway",
+                    STABILITY_TIMEOUT.as_millis()
+                );
+                return Ok((frame, now));
+            }
+            previous = Some((pixels, now));
+        }
+    }
+}
+
+fn read_frame(capture: &mut VideoCapture) -> opencv::Result<Mat> {
+    let mut frame = Mat::default();
+    if !capture.read(&mut frame)? || frame.empty() {
+        return Err(opencv::Error::new(
+            opencv::core::StsError,
+            "The camera didn't give a frame",
+        ));
+    }
+    Ok(frame)
+}
+
+/// Decodes a picture that a client pushed, in any format that `OpenCV` reads
+///
+/// # Errors
+///
+/// This function will return an error if the picture can't be decoded.
+pub fn decode_picture(bytes: &[u8]) -> opencv::Result<Mat> {
+    let picture = imgcodecs::imdecode(&bytes, IMREAD_COLOR)?;
+    if picture.empty() {
+        return Err(opencv::Error::new(
+            opencv::core::StsBadArg,
+            "Failed to decode the picture",
+        ));
+    }
+    Ok(picture)
+}
+
+/// Converts a BGR picture to the pixels that [`CVProcessor`] takes and resamples it to the processor's resolution, the same way the browser does. Processors that don't know their resolution get pictures resampled to [`DEFAULT_WIDTH`].
+///
+/// # Errors
+///
+/// This function will return an error if the resampled picture doesn't have as many pixels as the processor takes, which happens if the camera has a different aspect ratio than the one that pixels were assigned with, or if `OpenCV` fails.
+pub fn picture_pixels(
+    picture: &Mat,
+    cv_processor: &CVProcessor,
+) -> opencv::Result<Box<[(f64, f64, f64)]>> {
+    #[allow(clippy::cast_sign_loss)]
+    let (width, height) = (picture.cols() as usize, picture.rows() as usize);
+    let pixels = picture
+        .data_bytes()?
+        .chunks_exact(3)
+        .map(|bgr| {
+            (
+                f64::from(bgr[2]) / 255.0,
+                f64::from(bgr[1]) / 255.0,
+                f64::from(bgr[0]) / 255.0,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let resolution = cv_processor.resolution().unwrap_or_else(|| {
+        // The same rounding as the canvas in the browser
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss,
+            clippy::cast_precision_loss
+        )]
+        let height = (f64::from(DEFAULT_WIDTH) * height as f64 / width as f64).round() as usize;
+        (DEFAULT_WIDTH as usize, height)
+    });
+    let resampled = qvis::resample(&pixels, (width, height), resolution)
+        .map_err(|e| opencv::Error::new(opencv::core::StsBadSize, e.to_string()))?;
+    if resampled.len() != cv_processor.image_size() {
+        return Err(opencv::Error::new(
+            opencv::core::StsBadSize,
+            format!(
+                "Pictures are {}x{} = {} pixels but pixels were assigned on {} pixels",
+                resolution.0,
+                resolution.1,
+                resampled.len(),
+                cv_processor.image_size()
+            ),
+        ));
+    }
+    Ok(resampled)
+}
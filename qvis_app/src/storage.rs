@@ -0,0 +1,393 @@
+//! Where the server keeps its state between restarts
+
+use leptos::serde_json;
+use log::warn;
+use opencv::{
+    core::{CV_16UC1, Mat, Vector},
+    imgcodecs::{self, IMREAD_UNCHANGED},
+    prelude::*,
+};
+use qvis::{CVProcessor, Pixel};
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    app::{CheckpointInfo, ModelKind},
+    pixel_assignment::{PixelAssignmentLegend, from_label_map, to_label_map},
+};
+
+/// The environment variable holding the directory that the server keeps its state in
+pub const DATA_DIR_VAR: &str = "QVIS_DATA_DIR";
+const DEFAULT_DATA_DIR: &str = "qvis_data";
+/// The file in the data directory holding the processor that the server recognizes pictures with
+pub const CV_PROCESSOR_FILE: &str = "cv_processor.json";
+/// The directory in the data directory holding checkpoints of the processor
+pub const CHECKPOINTS_DIR: &str = "checkpoints";
+const CHECKPOINT_PREFIX: &str = "checkpoint-";
+const CHECKPOINT_EXTENSION: &str = ".json";
+/// The environment variable holding the directory that saved processors and pixel assignments are kept in
+pub const MODEL_DIR_VAR: &str = "QVIS_MODEL_DIR";
+const DEFAULT_MODEL_DIR: &str = "models";
+const MAX_MODEL_NAME_LEN: usize = 64;
+
+/// The directory that the server keeps its state in, which is [`DATA_DIR_VAR`] or `qvis_data` in the working directory
+pub fn data_dir() -> PathBuf {
+    std::env::var_os(DATA_DIR_VAR).map_or_else(|| PathBuf::from(DEFAULT_DATA_DIR), PathBuf::from)
+}
+
+/// Writes `contents` to `path` such that `path` is never left half written, even if the server crashes, by writing to a temporary file next to it and renaming it over `path`. Creates the parent directory if needed.
+///
+/// # Errors
+///
+/// This function will return an error if writing or renaming fails.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut tmp_name = path.file_name().unwrap_or_default().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut tmp_file = std::fs::File::create(&tmp_path)?;
+    tmp_file.write_all(contents)?;
+    tmp_file.sync_all()?;
+    drop(tmp_file);
+    std::fs::rename(&tmp_path, path)
+}
+
+/// The directory that saved processors and pixel assignments are kept in, which is [`MODEL_DIR_VAR`] or `models` in the data directory
+pub fn model_dir() -> PathBuf {
+    std::env::var_os(MODEL_DIR_VAR)
+        .map_or_else(|| data_dir().join(DEFAULT_MODEL_DIR), PathBuf::from)
+}
+
+fn kind_dir(kind: ModelKind) -> PathBuf {
+    model_dir().join(match kind {
+        ModelKind::CVProcessor => "cv_processors",
+        ModelKind::PixelAssignment => "pixel_assignments",
+    })
+}
+
+/// Checks that `name` is 1 to 64 ASCII letters, digits, `-` and `_`, which keeps clients from reaching outside of the model directory
+///
+/// # Errors
+///
+/// This function will return an [`io::ErrorKind::InvalidInput`] error if `name` isn't a valid name.
+pub fn validate_model_name(name: &str) -> io::Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_MODEL_NAME_LEN
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    if valid {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{name:?} isn't a valid name, which is 1 to {MAX_MODEL_NAME_LEN} letters, digits, `-` and `_`"
+            ),
+        ))
+    }
+}
+
+/// The path of the saved model with the given name, without an extension. Processors are saved as `.json` files and pixel assignments as a `.png` and a `.json` next to each other.
+///
+/// # Errors
+///
+/// This function will return an error if `name` isn't a valid name, see [`validate_model_name`].
+pub fn model_path(kind: ModelKind, name: &str) -> io::Result<PathBuf> {
+    validate_model_name(name)?;
+    Ok(kind_dir(kind).join(name))
+}
+
+/// Lists the names of every saved model of the given kind, in alphabetical order
+///
+/// # Errors
+///
+/// This function will return an error if the directory holding them exists but can't be read.
+pub fn list_models(kind: ModelKind) -> io::Result<Vec<String>> {
+    let entries = match std::fs::read_dir(kind_dir(kind)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut names = Vec::new();
+    for entry in entries {
+        let Ok(file_name) = entry?.file_name().into_string() else {
+            continue;
+        };
+        // Every kind has a `.json` file, and skipping anything else skips temporary files
+        let Some(name) = file_name.strip_suffix(".json") else {
+            continue;
+        };
+        if validate_model_name(name).is_ok() {
+            names.push(name.to_owned());
+        }
+    }
+    names.sort_unstable();
+    Ok(names)
+}
+
+/// Deletes the saved model with the given name
+///
+/// # Errors
+///
+/// This function will return an error if `name` isn't a valid name or if there is no such model.
+pub fn delete_model(kind: ModelKind, name: &str) -> io::Result<()> {
+    let path = model_path(kind, name)?;
+    std::fs::remove_file(path.with_extension("json"))?;
+    if kind == ModelKind::PixelAssignment {
+        std::fs::remove_file(path.with_extension("png")).or_else(|e| {
+            if e.kind() == io::ErrorKind::NotFound {
+                Ok(())
+            } else {
+                Err(e)
+            }
+        })?;
+    }
+    Ok(())
+}
+
+/// Saves `cv_processor` under the given name, replacing any processor saved under it before
+///
+/// # Errors
+///
+/// This function will return an error if `name` isn't a valid name or if the processor can't be written.
+pub fn save_cv_processor(name: &str, cv_processor: &CVProcessor) -> io::Result<()> {
+    let path = model_path(ModelKind::CVProcessor, name)?;
+    let json = serde_json::to_vec(cv_processor)?;
+    write_atomically(&path.with_extension("json"), &json)
+}
+
+/// Loads the processor saved under the given name
+///
+/// # Errors
+///
+/// This function will return an error if `name` isn't a valid name or if the processor can't be read.
+pub fn load_cv_processor(name: &str) -> io::Result<CVProcessor> {
+    let path = model_path(ModelKind::CVProcessor, name)?;
+    let file = std::fs::File::open(path.with_extension("json"))?;
+    Ok(serde_json::from_reader(io::BufReader::new(file))?)
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |since_epoch| {
+        since_epoch.as_millis().try_into().unwrap_or(u64::MAX)
+    })
+}
+
+/// Checkpoints are named `checkpoint-<unix time in milliseconds>-<calibrations>.json`, so they can be listed without reading them and sort by age
+fn parse_checkpoint_name(name: &str) -> Option<(u64, usize)> {
+    let stem = name
+        .strip_prefix(CHECKPOINT_PREFIX)?
+        .strip_suffix(CHECKPOINT_EXTENSION)?;
+    let (saved_at, calibrations) = stem.split_once('-')?;
+    // Reject anything that `parse` accepts but that isn't plain digits, like a leading `+`
+    if !saved_at
+        .bytes()
+        .chain(calibrations.bytes())
+        .all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+    Some((saved_at.parse().ok()?, calibrations.parse().ok()?))
+}
+
+/// Saves `cv_processor` as a new checkpoint, then deletes the oldest checkpoints so that at most `kept` remain
+///
+/// # Errors
+///
+/// This function will return an error if the checkpoint can't be written. Failing to delete old checkpoints is only logged.
+pub fn save_checkpoint(cv_processor: &CVProcessor, kept: usize) -> io::Result<CheckpointInfo> {
+    let now = SystemTime::now();
+    let calibrations = cv_processor.calibrations();
+    let name = format!(
+        "{CHECKPOINT_PREFIX}{}-{calibrations}{CHECKPOINT_EXTENSION}",
+        unix_millis(now)
+    );
+    let json = serde_json::to_vec(cv_processor)?;
+    write_atomically(&data_dir().join(CHECKPOINTS_DIR).join(&name), &json)?;
+
+    for old in list_checkpoints()?.iter().skip(kept.max(1)) {
+        let path = data_dir().join(CHECKPOINTS_DIR).join(&old.name);
+        if let Err(e) = std::fs::remove_file(&path) {
+            warn!("Failed to delete old checkpoint {}: {e}", path.display());
+        }
+    }
+
+    Ok(CheckpointInfo {
+        name,
+        calibrations,
+        age_secs: 0,
+    })
+}
+
+/// Lists every checkpoint, newest first
+///
+/// # Errors
+///
+/// This function will return an error if the checkpoint directory exists but can't be read.
+pub fn list_checkpoints() -> io::Result<Vec<CheckpointInfo>> {
+    let entries = match std::fs::read_dir(data_dir().join(CHECKPOINTS_DIR)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let now = unix_millis(SystemTime::now());
+
+    let mut checkpoints = Vec::new();
+    for entry in entries {
+        let Ok(name) = entry?.file_name().into_string() else {
+            continue;
+        };
+        // Skips the temporary files of checkpoints that are being written
+        let Some((saved_at, calibrations)) = parse_checkpoint_name(&name) else {
+            continue;
+        };
+        checkpoints.push((saved_at, name, calibrations));
+    }
+    checkpoints.sort_unstable_by(|a, b| b.cmp(a));
+
+    Ok(checkpoints
+        .into_iter()
+        .map(|(saved_at, name, calibrations)| CheckpointInfo {
+            name,
+            calibrations,
+            age_secs: now.saturating_sub(saved_at) / 1000,
+        })
+        .collect())
+}
+
+/// Loads the checkpoint with the given name, as listed by [`list_checkpoints`]
+///
+/// # Errors
+///
+/// This function will return an error if `name` isn't the name of a checkpoint or if the checkpoint can't be read.
+pub fn load_checkpoint(name: &str) -> io::Result<CVProcessor> {
+    if parse_checkpoint_name(name).is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{name:?} isn't the name of a checkpoint"),
+        ));
+    }
+    let file = std::fs::File::open(data_dir().join(CHECKPOINTS_DIR).join(name))?;
+    Ok(serde_json::from_reader(io::BufReader::new(file))?)
+}
+
+/// Saves a pixel assignment of an image `width` pixels wide as a 16 bit PNG label map at `{path}.png` and its legend at `{path}.json`
+///
+/// # Errors
+///
+/// This function will return an `OpenCV` error or an error if the files could not be written.
+pub fn save_pixel_assignment(
+    path: &Path,
+    pixel_assignment: &[Pixel],
+    width: usize,
+) -> Result<(), opencv::Error> {
+    let (label_map, legend) = to_label_map(pixel_assignment, width);
+    let label_map = Mat::new_rows_cols_with_data(
+        legend.height.try_into().map_err(to_opencv_error)?,
+        legend.width.try_into().map_err(to_opencv_error)?,
+        &label_map,
+    )?
+    .try_clone()?;
+    let png_path = path.with_extension("png");
+    let mut png = Vector::<u8>::new();
+    if !imgcodecs::imencode_def(".png", &label_map, &mut png)? {
+        return Err(opencv::Error::new(
+            opencv::core::StsError,
+            format!("Could not encode {}", png_path.display()),
+        ));
+    }
+    write_atomically(&png_path, png.as_slice()).map_err(to_opencv_error)?;
+    let legend = serde_json::to_vec(&legend).map_err(to_opencv_error)?;
+    write_atomically(&path.with_extension("json"), &legend).map_err(to_opencv_error)?;
+    Ok(())
+}
+
+/// Loads a pixel assignment saved by [`save_pixel_assignment`], returning it along with the width of the image that it is for
+///
+/// # Errors
+///
+/// This function will return an `OpenCV` error or an error if the files could not be read or don't match.
+pub fn load_pixel_assignment(path: &Path) -> Result<(Box<[Pixel]>, usize), opencv::Error> {
+    let legend = std::fs::read_to_string(path.with_extension("json")).map_err(to_opencv_error)?;
+    let legend: PixelAssignmentLegend =
+        leptos::serde_json::from_str(&legend).map_err(to_opencv_error)?;
+    let png_path = path.with_extension("png");
+    let label_map = imgcodecs::imread(&png_path.to_string_lossy(), IMREAD_UNCHANGED)?;
+    if label_map.empty() || label_map.typ() != CV_16UC1 {
+        return Err(opencv::Error::new(
+            opencv::core::StsError,
+            format!("{} is not a 16 bit grayscale image", png_path.display()),
+        ));
+    }
+    let pixel_assignment =
+        from_label_map(label_map.data_typed::<u16>()?, &legend).map_err(to_opencv_error)?;
+    Ok((pixel_assignment, legend.width))
+}
+
+fn to_opencv_error(e: impl std::fmt::Display) -> opencv::Error {
+    opencv::Error::new(opencv::core::StsError, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MAX_MODEL_NAME_LEN, parse_checkpoint_name, validate_model_name};
+
+    #[test]
+    fn test_validate_model_name() {
+        let longest = "x".repeat(MAX_MODEL_NAME_LEN);
+        let too_long = "x".repeat(MAX_MODEL_NAME_LEN + 1);
+
+        for name in ["rig", "Rig_2", "cube-3x3", "a", longest.as_str()] {
+            assert!(validate_model_name(name).is_ok(), "{name:?}");
+        }
+
+        for name in [
+            "",
+            "../x",
+            "..",
+            "a/b",
+            "a\\b",
+            "a.json",
+            "with space",
+            "café",
+            "名前",
+            too_long.as_str(),
+        ] {
+            let e = validate_model_name(name).unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput, "{name:?}");
+        }
+    }
+
+    #[test]
+    fn test_parse_checkpoint_name() {
+        assert_eq!(
+            parse_checkpoint_name("checkpoint-1700000000000-42.json"),
+            Some((1_700_000_000_000, 42))
+        );
+        assert_eq!(parse_checkpoint_name("checkpoint-0-0.json"), Some((0, 0)));
+
+        for name in [
+            "checkpoint-+1-2.json",
+            "checkpoint-1-+2.json",
+            "checkpoint--2.json",
+            "checkpoint-1-.json",
+            "checkpoint-1-2-3.json",
+            "checkpoint-1-2.json.tmp",
+            "checkpoint-1.json",
+            "checkpoint-a-2.json",
+            "../checkpoint-1-2.json",
+            "cv_processor.json",
+            "",
+        ] {
+            assert_eq!(parse_checkpoint_name(name), None, "{name:?}");
+        }
+    }
+}
@@ -1,17 +1,65 @@
+use crate::pixel_assignment_editor::AssignmentImage;
 use leptos::{html, prelude::*};
 use leptos_use::{UseUserMediaReturn, use_event_listener};
 use log::{info, warn};
 use qvis::CVProcessor;
-use std::sync::{
-    Arc,
-    atomic::{AtomicBool, Ordering},
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
 };
 use tokio::sync::{Notify, watch::Receiver};
-use wasm_bindgen::{Clamped, JsCast, JsValue, prelude::Closure};
+use wasm_bindgen::{Clamped, JsCast, JsValue};
 use wasm_bindgen_futures::{JsFuture, spawn_local};
 use web_sys::js_sys;
 
-const WIDTH: u32 = 850;
+/// The width that pictures are taken at unless the processor knows its own resolution, in pixels. The height follows from the aspect ratio of the camera. Processors from before they knew their resolution were all made for this width.
+pub const DEFAULT_WIDTH: u32 = 850;
+
+/// How long live recognition waits after recognizing a frame before capturing the next one, in milliseconds. Recognizing a frame takes a while, so recognizing every frame would make the page unresponsive.
+#[cfg(feature = "hydrate")]
+const LIVE_RECOGNITION_INTERVAL_MS: u32 = 500;
+/// The color to paint stickers that something covered, as RGBA
+#[cfg(feature = "hydrate")]
+const OCCLUDED_COLOR: [u8; 4] = [128, 128, 128, 160];
+
+/// How long to wait between the frames of a burst, in milliseconds, which is about one frame at 30 frames per second
+const BURST_FRAME_INTERVAL_MS: u32 = 35;
+/// How much the assigned pixels of a frame in a burst may differ from the rest of the burst on average before the frame is left out, see [`CVProcessor::combine_burst`]
+const BURST_TOLERANCE: f64 = 0.04;
+
+/// How long to wait between the frames that are compared to tell whether the puzzle holds still, in milliseconds
+pub(crate) const STABILITY_FRAME_INTERVAL_MS: u32 = 50;
+/// How much the assigned pixels may change between frames on average while the puzzle counts as holding still
+pub(crate) const STABILITY_TOLERANCE: f64 = 0.02;
+/// How long to wait for the puzzle to hold still at most before taking the picture anyway
+pub(crate) const STABILITY_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long the puzzle has to hold still before a robot's picture is taken, in milliseconds, unless the browser is told otherwise
+pub const DEFAULT_STABLE_MS: u32 = 300;
+
+/// Resolutions to offer for the camera, as width and height
+const CAMERA_RESOLUTIONS: [(u32, u32); 4] = [(640, 480), (1280, 720), (1920, 1080), (3840, 2160)];
+
+/// How the camera is set up, which is saved with every profile so that pictures can be taken under the same conditions again
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraSettings {
+    /// The camera to use, or `None` for the one facing the environment
+    pub device_id: Option<String>,
+    /// The width and height to ask the camera for, or `None` for its default
+    pub resolution: Option<(u32, u32)>,
+    /// The zoom to ask the camera for, or `None` for its default
+    pub zoom: Option<f64>,
+    /// Whether to keep the exposure where it is instead of letting the camera adjust it
+    pub lock_exposure: bool,
+    /// Whether to keep the white balance where it is instead of letting the camera adjust it
+    pub lock_white_balance: bool,
+    /// Whether to keep the focus where it is instead of letting the camera adjust it
+    pub lock_focus: bool,
+}
 
 #[derive(Default)]
 pub struct OnceBarrier {
@@ -41,26 +89,86 @@ impl OnceBarrier {
     }
 }
 
-async fn draw_video_on_canvas(
-    canvas_ref: &web_sys::HtmlCanvasElement,
-    video_ref: &web_sys::HtmlVideoElement,
-    video_enabled: Signal<bool>,
-    set_video_enabled: WriteSignal<bool>,
-    playing_barrier: &OnceBarrier,
-) -> web_sys::CanvasRenderingContext2d {
+fn context_2d(canvas: &web_sys::HtmlCanvasElement) -> web_sys::CanvasRenderingContext2d {
     let opts = js_sys::Object::new();
     js_sys::Reflect::set(&opts, &"willReadFrequently".into(), &true.into()).unwrap();
     js_sys::Reflect::set(&opts, &"alpha".into(), &false.into()).unwrap();
-    let ctx = canvas_ref
+    canvas
         .get_context_with_context_options("2d", &opts)
         .unwrap()
         .unwrap()
         .dyn_into::<web_sys::CanvasRenderingContext2d>()
-        .unwrap();
+        .unwrap()
+}
+
+/// Draws the current frame of the video on the canvas, resampled to the size of the canvas with [`qvis::resample`]. The frame is drawn at the camera's own resolution first and resampled here, because browsers scale images in their own ways and the server has to resample pictures the same way.
+async fn draw_video_on_canvas(
+    canvas_ref: &web_sys::HtmlCanvasElement,
+    video_ref: &web_sys::HtmlVideoElement,
+    video_enabled: Signal<bool>,
+    set_video_enabled: WriteSignal<bool>,
+    playing_barrier: &OnceBarrier,
+) -> web_sys::CanvasRenderingContext2d {
+    let ctx = context_2d(canvas_ref);
     if !video_enabled.get_untracked() {
         set_video_enabled.set(true);
     }
     playing_barrier.wait().await;
+
+    let (video_width, video_height) = (video_ref.video_width(), video_ref.video_height());
+    let frame_canvas = document()
+        .create_element("canvas")
+        .unwrap()
+        .dyn_into::<web_sys::HtmlCanvasElement>()
+        .unwrap();
+    frame_canvas.set_width(video_width);
+    frame_canvas.set_height(video_height);
+    let frame_ctx = context_2d(&frame_canvas);
+    frame_ctx
+        .draw_image_with_html_video_element(video_ref, 0.0, 0.0)
+        .unwrap();
+    let frame = frame_ctx
+        .get_image_data(0.0, 0.0, video_width.into(), video_height.into())
+        .unwrap();
+
+    let (width, height) = (canvas_ref.width(), canvas_ref.height());
+    let pixels = match qvis::resample(
+        &image_pixels(&frame),
+        (video_width as usize, video_height as usize),
+        (width as usize, height as usize),
+    ) {
+        Ok(pixels) => pixels,
+        Err(e) => {
+            warn!("Failed to resample the frame, letting the browser scale it: {e}");
+            draw_scaled_video(&ctx, canvas_ref, video_ref);
+            return ctx;
+        }
+    };
+    #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+    let data = pixels
+        .iter()
+        .flat_map(|&(r, g, b)| {
+            [
+                (r * 255.0).round() as u8,
+                (g * 255.0).round() as u8,
+                (b * 255.0).round() as u8,
+                255,
+            ]
+        })
+        .collect::<Vec<_>>();
+    let image_data =
+        web_sys::ImageData::new_with_u8_clamped_array_and_sh(Clamped(&data), width, height)
+            .unwrap();
+    ctx.put_image_data(&image_data, 0.0, 0.0).unwrap();
+    ctx
+}
+
+/// Draws the current frame of the video on the canvas, scaled to fit it by the browser
+fn draw_scaled_video(
+    ctx: &web_sys::CanvasRenderingContext2d,
+    canvas_ref: &web_sys::HtmlCanvasElement,
+    video_ref: &web_sys::HtmlVideoElement,
+) {
     ctx.draw_image_with_html_video_element_and_dw_and_dh(
         video_ref,
         0.0,
@@ -69,25 +177,23 @@ async fn draw_video_on_canvas(
         canvas_ref.height().into(),
     )
     .unwrap();
-    ctx
 }
 
-pub(crate) async fn take_picture_command(
+/// Takes a picture that is only good for comparing with other pictures taken this way, scaled by the browser instead of [`qvis::resample`] because resampling every frame in WASM is slow
+async fn take_comparison_picture(
     video_ref: &web_sys::HtmlVideoElement,
     canvas_ref: &web_sys::HtmlCanvasElement,
     video_enabled: Signal<bool>,
     set_video_enabled: WriteSignal<bool>,
     playing_barrier: &OnceBarrier,
 ) -> Box<[(f64, f64, f64)]> {
-    let ctx = draw_video_on_canvas(
-        canvas_ref,
-        video_ref,
-        video_enabled,
-        set_video_enabled,
-        playing_barrier,
-    )
-    .await;
+    let ctx = context_2d(canvas_ref);
+    if !video_enabled.get_untracked() {
+        set_video_enabled.set(true);
+    }
+    playing_barrier.wait().await;
 
+    draw_scaled_video(&ctx, canvas_ref, video_ref);
     let image_data = ctx
         .get_image_data(
             0.0,
@@ -96,11 +202,71 @@ pub(crate) async fn take_picture_command(
             canvas_ref.height().into(),
         )
         .unwrap();
-    let data = &*image_data.data();
+    image_pixels(&image_data)
+}
 
-    info!("Captured image data length: {}", data.len());
+async fn capture_image_data(
+    video_ref: &web_sys::HtmlVideoElement,
+    canvas_ref: &web_sys::HtmlCanvasElement,
+    video_enabled: Signal<bool>,
+    set_video_enabled: WriteSignal<bool>,
+    playing_barrier: &OnceBarrier,
+) -> web_sys::ImageData {
+    let ctx = draw_video_on_canvas(
+        canvas_ref,
+        video_ref,
+        video_enabled,
+        set_video_enabled,
+        playing_barrier,
+    )
+    .await;
 
-    data.chunks_exact(4)
+    ctx.get_image_data(
+        0.0,
+        0.0,
+        canvas_ref.width().into(),
+        canvas_ref.height().into(),
+    )
+    .unwrap()
+}
+
+/// A picture taken from the canvas, along with the width and height that it was taken at
+pub(crate) struct Picture {
+    pub(crate) pixels: Box<[(f64, f64, f64)]>,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+}
+
+pub(crate) async fn take_picture_command(
+    video_ref: &web_sys::HtmlVideoElement,
+    canvas_ref: &web_sys::HtmlCanvasElement,
+    video_enabled: Signal<bool>,
+    set_video_enabled: WriteSignal<bool>,
+    playing_barrier: &OnceBarrier,
+) -> Picture {
+    let image_data = capture_image_data(
+        video_ref,
+        canvas_ref,
+        video_enabled,
+        set_video_enabled,
+        playing_barrier,
+    )
+    .await;
+    let pixels = image_pixels(&image_data);
+
+    info!("Captured image data length: {}", 4 * pixels.len());
+
+    Picture {
+        pixels,
+        width: image_data.width() as usize,
+        height: image_data.height() as usize,
+    }
+}
+
+fn image_pixels(image_data: &web_sys::ImageData) -> Box<[(f64, f64, f64)]> {
+    image_data
+        .data()
+        .chunks_exact(4)
         .map(|rgba| {
             let [r, g, b, _] = rgba.try_into().unwrap();
             (
@@ -113,69 +279,479 @@ pub(crate) async fn take_picture_command(
         .into_boxed_slice()
 }
 
-pub(crate) async fn pixel_assignment_command(
+/// The average difference between the pixels of two pictures, only counting the pixels marked in `locations` if it fits them
+pub(crate) fn picture_difference(
+    a: &[(f64, f64, f64)],
+    b: &[(f64, f64, f64)],
+    locations: Option<&[bool]>,
+) -> f64 {
+    let locations = locations.filter(|locations| locations.len() == a.len());
+    let (total, count) = a
+        .iter()
+        .zip(b)
+        .enumerate()
+        .filter(|(idx, _)| locations.is_none_or(|locations| locations[*idx]))
+        .fold((0.0, 0usize), |(total, count), (_, (a, b))| {
+            let difference = (a.0 - b.0).abs() + (a.1 - b.1).abs() + (a.2 - b.2).abs();
+            (total + difference / 3.0, count + 1)
+        });
+    if count == 0 {
+        return 0.0;
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let count = count as f64;
+    total / count
+}
+
+/// Waits until the pixels marked in `locations`, or every pixel if there are none, stay the same for `stable_for`, which lets the puzzle settle after a robot moved it. Gives up after [`STABILITY_TIMEOUT`]. Returns how long it waited.
+pub(crate) async fn wait_until_stable_command(
     video_ref: &web_sys::HtmlVideoElement,
     canvas_ref: &web_sys::HtmlCanvasElement,
     video_enabled: Signal<bool>,
     set_video_enabled: WriteSignal<bool>,
     playing_barrier: &OnceBarrier,
-) -> web_sys::Blob {
-    draw_video_on_canvas(
-        canvas_ref,
+    locations: Option<&[bool]>,
+    stable_for: Duration,
+) -> Duration {
+    if stable_for.is_zero() {
+        return Duration::ZERO;
+    }
+    // Milliseconds since the Unix epoch
+    let start = js_sys::Date::now();
+    let elapsed = || Duration::from_secs_f64((js_sys::Date::now() - start).max(0.0) / 1000.0);
+
+    let mut previous = None::<Box<[(f64, f64, f64)]>>;
+    let mut stable_since = Duration::ZERO;
+    loop {
+        let picture = take_comparison_picture(
+            video_ref,
+            canvas_ref,
+            video_enabled,
+            set_video_enabled,
+            playing_barrier,
+        )
+        .await;
+        let now = elapsed();
+        let moved = previous.as_ref().is_none_or(|previous| {
+            previous.len() != picture.len()
+                || picture_difference(previous, &picture, locations) > STABILITY_TOLERANCE
+        });
+        if moved {
+            stable_since = now;
+        } else if now - stable_since >= stable_for {
+            info!("The puzzle held still after {} ms", now.as_millis());
+            return now;
+        }
+        if now >= STABILITY_TIMEOUT {
+            warn!(
+                "The puzzle didn't hold still for {} ms, taking the picture anyway",
+                STABILITY_TIMEOUT.as_millis()
+            );
+            return now;
+        }
+        previous = Some(picture);
+        gloo_timers::future::TimeoutFuture::new(STABILITY_FRAME_INTERVAL_MS).await;
+    }
+}
+
+/// Takes `frames` pictures in quick succession, to be combined with [`combine_burst`]
+pub(crate) async fn take_burst_command(
+    video_ref: &web_sys::HtmlVideoElement,
+    canvas_ref: &web_sys::HtmlCanvasElement,
+    video_enabled: Signal<bool>,
+    set_video_enabled: WriteSignal<bool>,
+    playing_barrier: &OnceBarrier,
+    frames: usize,
+) -> Vec<Picture> {
+    let mut burst = Vec::with_capacity(frames);
+    for i in 0..frames.max(1) {
+        if i > 0 {
+            gloo_timers::future::TimeoutFuture::new(BURST_FRAME_INTERVAL_MS).await;
+        }
+        burst.push(
+            take_picture_command(
+                video_ref,
+                canvas_ref,
+                video_enabled,
+                set_video_enabled,
+                playing_barrier,
+            )
+            .await,
+        );
+    }
+    burst
+}
+
+/// Resamples a burst of pictures to the resolution of the processor with [`CVProcessor::fit_image`] and combines them into one with less noise, leaving out frames where something moved. Returns `None` if the pictures can't be made to fit the processor, which happens when it doesn't know its resolution and the pictures were taken at another one.
+pub(crate) fn combine_burst(
+    cv_processor: &CVProcessor,
+    burst: Vec<Picture>,
+) -> Option<Box<[(f64, f64, f64)]>> {
+    let mut burst = burst
+        .into_iter()
+        .map(|picture| cv_processor.fit_image(&picture.pixels, picture.width, picture.height))
+        .collect::<Option<Vec<_>>>()?;
+    if burst.len() == 1 {
+        return burst.pop();
+    }
+    let combined = cv_processor.combine_burst(&burst, BURST_TOLERANCE);
+    info!("Averaged {} of {} frames", combined.kept.len(), burst.len());
+    Some(combined.image)
+}
+
+pub(crate) async fn assignment_image_command(
+    video_ref: &web_sys::HtmlVideoElement,
+    canvas_ref: &web_sys::HtmlCanvasElement,
+    video_enabled: Signal<bool>,
+    set_video_enabled: WriteSignal<bool>,
+    playing_barrier: &OnceBarrier,
+) -> AssignmentImage {
+    let image_data = capture_image_data(
         video_ref,
+        canvas_ref,
         video_enabled,
         set_video_enabled,
         playing_barrier,
     )
     .await;
 
-    let promise = js_sys::Promise::new(&mut |resolve, reject| {
-        let resolve = resolve.clone();
-        let closure = Closure::once(move |blob: Option<web_sys::Blob>| match blob {
-            Some(blob) => {
-                resolve.call1(&JsValue::NULL, &blob).unwrap();
+    AssignmentImage {
+        width: image_data.width() as usize,
+        height: image_data.height() as usize,
+        pixels: image_data
+            .data()
+            .chunks_exact(4)
+            .map(|rgba| [rgba[0], rgba[1], rgba[2]])
+            .collect(),
+    }
+}
+
+#[cfg(feature = "hydrate")]
+fn overlay_context(
+    cv_overlay_ref: &web_sys::HtmlCanvasElement,
+) -> web_sys::CanvasRenderingContext2d {
+    let opts = js_sys::Object::new();
+    js_sys::Reflect::set(&opts, &"willReadFrequently".into(), &true.into()).unwrap();
+    js_sys::Reflect::set(&opts, &"alpha".into(), &true.into()).unwrap();
+    cv_overlay_ref
+        .get_context_with_context_options("2d", &opts)
+        .unwrap()
+        .unwrap()
+        .dyn_into::<web_sys::CanvasRenderingContext2d>()
+        .unwrap()
+}
+
+/// Marks every pixel that the processor uses in magenta
+#[cfg(feature = "hydrate")]
+fn draw_assignment_overlay(
+    cv_overlay_ref: &web_sys::HtmlCanvasElement,
+    cv_processor: &CVProcessor,
+) {
+    let pixel_assignment = cv_processor.pixel_assignment_locations();
+    let mut overlay_data = vec![0u8; 4 * pixel_assignment.len()];
+    let mut assigned_pixels_count = 0;
+    for overlay_pixel_mut in overlay_data
+        .chunks_exact_mut(4)
+        .zip(pixel_assignment.iter())
+        .filter_map(|(overlay_pixel_mut, &assigned_pixel)| {
+            if assigned_pixel {
+                Some(overlay_pixel_mut)
+            } else {
+                None
             }
-            None => {
-                reject
-                    .call1(&JsValue::NULL, &JsValue::from_str("canvas toBlob failed"))
-                    .unwrap();
+        })
+    {
+        assigned_pixels_count += 1;
+        overlay_pixel_mut[0] = 255;
+        overlay_pixel_mut[1] = 0;
+        overlay_pixel_mut[2] = 255;
+        overlay_pixel_mut[3] = 255;
+    }
+    info!(
+        "Assigned {}/{} pixels",
+        assigned_pixels_count,
+        pixel_assignment.len()
+    );
+    let overlay_height = cv_overlay_ref.height();
+    let overlay_width = cv_overlay_ref.width();
+    assert_eq!(
+        overlay_height as usize * overlay_width as usize,
+        pixel_assignment.len()
+    );
+
+    let overlay_image_data = web_sys::ImageData::new_with_u8_clamped_array_and_sh(
+        Clamped(&overlay_data),
+        overlay_width,
+        overlay_height,
+    )
+    .unwrap();
+    overlay_context(cv_overlay_ref)
+        .put_image_data(&overlay_image_data, 0.0, 0.0)
+        .unwrap();
+}
+
+/// The color to paint stickers that were recognized as the given puzzle color
+#[cfg(feature = "hydrate")]
+fn display_color(color: &str) -> [u8; 3] {
+    match color {
+        "white" => [255, 255, 255],
+        "yellow" => [255, 235, 0],
+        "red" => [230, 0, 0],
+        "orange" => [255, 140, 0],
+        "blue" => [0, 80, 255],
+        "green" => [0, 200, 60],
+        _ => [128, 128, 128],
+    }
+}
+
+/// Paints every sticker in the color that it looks like, more opaque the more confident that is, and labels it with its confidence in percent. Stickers whose color disagrees with the matched state are outlined in magenta.
+#[cfg(feature = "hydrate")]
+fn draw_recognition_overlay(
+    cv_overlay_ref: &web_sys::HtmlCanvasElement,
+    assignment: &[qvis::Pixel],
+    confidence: f64,
+    stickers: &[qvis::StickerRecognition],
+) {
+    let overlay_width = cv_overlay_ref.width();
+    let overlay_height = cv_overlay_ref.height();
+    assert_eq!(
+        overlay_height as usize * overlay_width as usize,
+        assignment.len()
+    );
+    let width = overlay_width as usize;
+
+    let mut overlay_data = vec![0u8; 4 * assignment.len()];
+    // The bounding box of every sticker as (left, top, right, bottom)
+    let mut bounds = vec![None::<(usize, usize, usize, usize)>; stickers.len()];
+    for (idx, (overlay_pixel_mut, pixel)) in overlay_data
+        .chunks_exact_mut(4)
+        .zip(assignment.iter())
+        .enumerate()
+    {
+        let qvis::Pixel::Sticker(sticker) = *pixel else {
+            continue;
+        };
+        let recognition = &stickers[sticker];
+        if recognition.occluded {
+            overlay_pixel_mut.copy_from_slice(&OCCLUDED_COLOR);
+        } else {
+            let [r, g, b] = display_color(&recognition.likeliest_color);
+            #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+            let alpha = (64.0 + 191.0 * recognition.confidence).round() as u8;
+            overlay_pixel_mut.copy_from_slice(&[r, g, b, alpha]);
+        }
+
+        let (x, y) = (idx % width, idx / width);
+        bounds[sticker] = Some(match bounds[sticker] {
+            Some((left, top, right, bottom)) => {
+                (left.min(x), top.min(y), right.max(x), bottom.max(y))
             }
+            None => (x, y, x, y),
         });
-        canvas_ref
-            .to_blob_with_type_and_encoder_options(
-                closure.as_ref().unchecked_ref(),
-                "image/webp",
-                &JsValue::from_f64(1.0),
-            )
-            .unwrap();
-        closure.forget();
+    }
+
+    let overlay_image_data = web_sys::ImageData::new_with_u8_clamped_array_and_sh(
+        Clamped(&overlay_data),
+        overlay_width,
+        overlay_height,
+    )
+    .unwrap();
+    let ctx = overlay_context(cv_overlay_ref);
+    ctx.put_image_data(&overlay_image_data, 0.0, 0.0).unwrap();
+
+    ctx.set_font("bold 14px sans-serif");
+    ctx.set_text_align("center");
+    ctx.set_text_baseline("middle");
+    ctx.set_line_width(3.0);
+    for (recognition, bounds) in stickers.iter().zip(&bounds) {
+        let Some((left, top, right, bottom)) = *bounds else {
+            continue;
+        };
+        #[allow(clippy::cast_precision_loss)]
+        let (left, top, right, bottom) = (left as f64, top as f64, right as f64, bottom as f64);
+        if recognition.disagrees() {
+            ctx.set_stroke_style_str("magenta");
+            ctx.stroke_rect(
+                left - 2.0,
+                top - 2.0,
+                right - left + 5.0,
+                bottom - top + 5.0,
+            );
+        }
+        let label = if recognition.occluded {
+            "?".to_string()
+        } else {
+            format!("{:.0}", recognition.confidence * 100.)
+        };
+        let (x, y) = ((left + right) / 2.0, (top + bottom) / 2.0);
+        ctx.set_stroke_style_str("black");
+        ctx.stroke_text(&label, x, y).unwrap();
+        ctx.set_fill_style_str("white");
+        ctx.fill_text(&label, x, y).unwrap();
+    }
+
+    let summary = format!(
+        "Matched with confidence {:.2}, {} stickers disagree, {} stickers occluded",
+        confidence * 100.,
+        stickers
+            .iter()
+            .filter(|sticker| sticker.disagrees())
+            .count(),
+        stickers.iter().filter(|sticker| sticker.occluded).count()
+    );
+    ctx.set_text_align("left");
+    ctx.set_text_baseline("top");
+    ctx.set_stroke_style_str("black");
+    ctx.stroke_text(&summary, 8.0, 8.0).unwrap();
+    ctx.set_fill_style_str("white");
+    ctx.fill_text(&summary, 8.0, 8.0).unwrap();
+}
+
+/// The height of the pictures that the processor takes, if it knows its resolution and takes pictures of the given width
+fn processor_height(cv_processor: Option<&CVProcessor>, width: u32) -> Option<u32> {
+    let (processor_width, height) = cv_processor?.resolution()?;
+    if processor_width != width as usize {
+        return None;
+    }
+    u32::try_from(height).ok()
+}
+
+/// Sizes the canvases for pictures of the given width and height, and the video to match them. Without a height, it follows from the aspect ratio of the camera.
+fn resize_canvases(
+    video_ref: &web_sys::HtmlVideoElement,
+    canvas_ref: &web_sys::HtmlCanvasElement,
+    cv_overlay_ref: &web_sys::HtmlCanvasElement,
+    width: u32,
+    height: Option<u32>,
+) {
+    #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+    let height = height.unwrap_or_else(|| {
+        let video_width = f64::from(video_ref.video_width());
+        let video_height = f64::from(video_ref.video_height());
+
+        let aspect = video_height / video_width;
+        (f64::from(width) * aspect).round() as u32
     });
-    let blob = JsFuture::from(promise).await.unwrap();
-    blob.dyn_into::<web_sys::Blob>().unwrap()
+
+    for element in [
+        canvas_ref.unchecked_ref::<web_sys::Element>(),
+        video_ref.unchecked_ref(),
+        cv_overlay_ref.unchecked_ref(),
+    ] {
+        element.set_attribute("width", &width.to_string()).unwrap();
+        element
+            .set_attribute("height", &height.to_string())
+            .unwrap();
+    }
+}
+
+/// Returns the ID and label of every camera. Labels are empty until the user allows camera access.
+async fn all_camera_devices() -> Result<Vec<(String, String)>, JsValue> {
+    let media_devices = web_sys::window()
+        .ok_or_else(|| JsValue::from_str("Failed to access window"))?
+        .navigator()
+        .media_devices()?;
+
+    let devices_promise = media_devices.enumerate_devices()?;
+    let devices_js = JsFuture::from(devices_promise).await?;
+    let devices_array = js_sys::Array::from(&devices_js);
+
+    Ok(devices_array
+        .iter()
+        .filter_map(|device_js| {
+            let device_info: web_sys::MediaDeviceInfo = device_js.dyn_into().ok()?;
+            if device_info.kind() == web_sys::MediaDeviceKind::Videoinput {
+                Some((device_info.device_id(), device_info.label()))
+            } else {
+                None
+            }
+        })
+        .collect())
 }
 
-// async fn all_camera_devices() -> Result<Vec<SendWrapper<web_sys::MediaDeviceInfo>>, JsValue> {
-//     let media_devices = web_sys::window()
-//         .ok_or_else(|| JsValue::from_str("Failed to access window"))?
-//         .navigator()
-//         .media_devices()?;
-
-//     let devices_promise = media_devices.enumerate_devices()?;
-//     let devices_js = JsFuture::from(devices_promise).await?;
-//     let devices_array = js_sys::Array::from(&devices_js);
-
-//     Ok(devices_array
-//         .iter()
-//         .filter_map(|device_js| {
-//             let device_info: web_sys::MediaDeviceInfo = device_js.dyn_into().ok()?;
-//             if device_info.kind() == web_sys::MediaDeviceKind::Videoinput {
-//                 Some(SendWrapper::new(device_info))
-//             } else {
-//                 None
-//             }
-//         })
-//         .collect())
-// }
+/// Builds the `MediaTrackConstraints` for the given settings
+fn track_constraints(settings: &CameraSettings) -> js_sys::Object {
+    fn object(entries: &[(&str, JsValue)]) -> js_sys::Object {
+        let object = js_sys::Object::new();
+        for (key, value) in entries {
+            js_sys::Reflect::set(&object, &(*key).into(), value).unwrap();
+        }
+        object
+    }
+
+    let mut entries = vec![match &settings.device_id {
+        Some(device_id) => (
+            "deviceId",
+            object(&[("exact", device_id.as_str().into())]).into(),
+        ),
+        None => (
+            "facingMode",
+            object(&[("ideal", "environment".into())]).into(),
+        ),
+    }];
+    if let Some((width, height)) = settings.resolution {
+        entries.push(("width", object(&[("ideal", width.into())]).into()));
+        entries.push(("height", object(&[("ideal", height.into())]).into()));
+    }
+
+    let mode = |lock| JsValue::from_str(if lock { "manual" } else { "continuous" });
+    // Each advanced constraint is in a set of its own, because cameras ignore a whole set if they can't satisfy all of it
+    let advanced = js_sys::Array::new();
+    if let Some(zoom) = settings.zoom {
+        advanced.push(&object(&[("zoom", zoom.into())]));
+    }
+    advanced.push(&object(&[("exposureMode", mode(settings.lock_exposure))]));
+    advanced.push(&object(&[(
+        "whiteBalanceMode",
+        mode(settings.lock_white_balance),
+    )]));
+    advanced.push(&object(&[("focusMode", mode(settings.lock_focus))]));
+    entries.push(("advanced", advanced.into()));
+
+    object(&entries)
+}
+
+/// Applies the settings to the stream that the video is showing, replacing it with a stream from another camera if they ask for one
+async fn apply_camera_settings(
+    video: &web_sys::HtmlVideoElement,
+    settings: &CameraSettings,
+) -> Result<(), JsValue> {
+    let Some(stream) = video.src_object() else {
+        return Ok(());
+    };
+    let Ok(track) = stream
+        .get_video_tracks()
+        .get(0)
+        .dyn_into::<web_sys::MediaStreamTrack>()
+    else {
+        return Ok(());
+    };
+    let constraints = track_constraints(settings);
+
+    let current_device_id =
+        js_sys::Reflect::get(&track.get_settings(), &"deviceId".into())?.as_string();
+    if settings.device_id.is_some() && settings.device_id != current_device_id {
+        let stream_constraints = web_sys::MediaStreamConstraints::default();
+        stream_constraints.set_video(&constraints);
+        let new_stream = JsFuture::from(
+            web_sys::window()
+                .ok_or_else(|| JsValue::from_str("Failed to access window"))?
+                .navigator()
+                .media_devices()?
+                .get_user_media_with_constraints(&stream_constraints)?,
+        )
+        .await?
+        .dyn_into::<web_sys::MediaStream>()?;
+        for track in stream.get_tracks().iter() {
+            track.unchecked_into::<web_sys::MediaStreamTrack>().stop();
+        }
+        video.set_src_object(Some(&new_stream));
+        return Ok(());
+    }
+
+    JsFuture::from(track.apply_constraints_with_constraints(constraints.unchecked_ref())?).await?;
+    Ok(())
+}
 
 #[component]
 pub fn Video(
@@ -188,20 +764,23 @@ pub fn Video(
     >,
     playing_barrier: Arc<OnceBarrier>,
     mut cv_available_rx: Receiver<Option<CVProcessor>>,
+    /// Whether to keep recognizing what the camera sees and draw it over the video
+    #[prop(into)]
+    live_recognition: Signal<bool>,
+    camera_settings: RwSignal<CameraSettings>,
+    /// The width to take pictures at, see [`DEFAULT_WIDTH`]
+    #[prop(into)]
+    capture_width: Signal<u32>,
 ) -> impl IntoView {
     let UseUserMediaReturn {
         stream,
+        enabled,
         set_enabled,
         ..
     } = use_user_media_return;
     drop(use_user_media_return);
 
     Effect::new(move |_| {
-        // let media = use_window()
-        //     .navigator()
-        //     .ok_or_else(|| JsValue::from_str("Failed to access window.navigator"))
-        //     .and_then(|n| n.media_devices())
-        //     .unwrap();
         let video_ref = video_ref.get().unwrap();
         let stream = stream.read();
         let maybe_stream = match stream.as_ref() {
@@ -240,92 +819,134 @@ pub fn Video(
     let a = Arc::new(Notify::new());
     {
         let a = Arc::clone(&a);
+        let cv_available_rx = cv_available_rx.clone();
         let _ = use_event_listener(video_ref, leptos::ev::loadedmetadata, move |_| {
             let video_ref = video_ref.get().unwrap();
             let canvas_ref = canvas_ref.get().unwrap();
             let cv_overlay_ref = cv_overlay_ref.get().unwrap();
 
-            let video_width = f64::from(video_ref.video_width());
-            let video_height = f64::from(video_ref.video_height());
-
-            let aspect = video_height / video_width;
-            #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
-            let height = (f64::from(WIDTH) * aspect).round() as u32;
-
-            canvas_ref
-                .set_attribute("width", &WIDTH.to_string())
-                .unwrap();
-            canvas_ref
-                .set_attribute("height", &height.to_string())
-                .unwrap();
-            video_ref
-                .set_attribute("width", &WIDTH.to_string())
-                .unwrap();
-            video_ref
-                .set_attribute("height", &height.to_string())
-                .unwrap();
-            cv_overlay_ref
-                .set_attribute("width", &WIDTH.to_string())
-                .unwrap();
-            cv_overlay_ref
-                .set_attribute("height", &height.to_string())
-                .unwrap();
+            let width = capture_width.get_untracked();
+            resize_canvases(
+                &video_ref,
+                &canvas_ref,
+                &cv_overlay_ref,
+                width,
+                processor_height(cv_available_rx.borrow().as_ref(), width),
+            );
             a.notify_one();
         });
     }
 
-    let _ = use_event_listener(video_ref, leptos::ev::playing, move |_| {
+    {
+        let cv_available_rx = cv_available_rx.clone();
+        Effect::new(move |_| {
+            let width = capture_width.get();
+            let video_ref = video_ref.get().unwrap();
+            // Wait for the video to know its size, which resizes the canvases anyway
+            if video_ref.video_width() == 0 {
+                return;
+            }
+            resize_canvases(
+                &video_ref,
+                &canvas_ref.get().unwrap(),
+                &cv_overlay_ref.get().unwrap(),
+                width,
+                processor_height(cv_available_rx.borrow().as_ref(), width),
+            );
+        });
+    }
+
+    let _ = use_event_listener(video_ref, leptos::ev::playing, {
         let playing_barrier = Arc::clone(&playing_barrier);
+        move |_| {
+            let playing_barrier = Arc::clone(&playing_barrier);
+            spawn_local(async move {
+                // let the camera exposure stabilize
+                gloo_timers::future::TimeoutFuture::new(1000).await;
+                playing_barrier.set_ready();
+            });
+        }
+    });
+
+    // Every new stream starts out with the camera's defaults, so the settings are applied to every new stream too
+    Effect::new(move |_| {
+        let settings = camera_settings.get();
+        stream.track();
         spawn_local(async move {
-            // let the camera exposure stabilize
-            gloo_timers::future::TimeoutFuture::new(1000).await;
-            playing_barrier.set_ready();
+            if let Err(e) =
+                apply_camera_settings(&video_ref.get_untracked().unwrap(), &settings).await
+            {
+                warn!("Failed to apply camera settings: {e:?}");
+            }
         });
     });
 
-    // let camera_devices =
-    //     LocalResource::new(move || async move { all_camera_devices().await.unwrap() });
-    // let camera_device =
-    //     LocalResource::new(move || async move { camera_devices.await.first().cloned() });
-
-    // let select_camera_device = move |ev: Targeted<web_sys::Event, web_sys::HtmlSelectElement>| {
-    //     let v = ev.target().value();
-    //     let selected_camera_device = camera_devices
-    //         .get()
-    //         .unwrap()
-    //         .iter()
-    //         .find(|d| d.device_id() == v)
-    //         .cloned();
-    //     *camera_device.write() = Some(selected_camera_device);
-
-    //     let a = web_sys::MediaTrackConstraints::default();
-
-    //     let b = web_sys::ConstrainDomStringParameters::default();
-    //     b.set_ideal(&JsValue::from_str("environment"));
-    //     a.set_facing_mode(&b);
-
-    //     let b = web_sys::ConstrainDomStringParameters::default();
-    //     b.set_exact(&JsValue::from_str(&v));
-    //     a.set_device_id(&b);
-
-    //     let c = web_sys::MediaStreamConstraints::default();
-    //     c.set_video(&a);
-
-    //     spawn_local(async move {
-    //         if let Some(device) = stream.get_untracked() {
-    //             for track in device.unwrap().get_tracks() {
-    //                 wasm_bindgen_futures::JsFuture::from(
-    //                     track
-    //                         .unchecked_ref::<web_sys::MediaStreamTrack>()
-    //                         .apply_constraints_with_constraints(c.unchecked_ref())
-    //                         .unwrap(),
-    //                 )
-    //                 .await
-    //                 .unwrap();
-    //             }
-    //         }
-    //     });
-    // };
+    let camera_devices = LocalResource::new(move || {
+        // Labels become available once the camera is allowed
+        stream.track();
+        async move {
+            all_camera_devices().await.unwrap_or_else(|e| {
+                warn!("Failed to list cameras: {e:?}");
+                Vec::new()
+            })
+        }
+    });
+
+    #[cfg(feature = "hydrate")]
+    {
+        let cv_available_rx = cv_available_rx.clone();
+        spawn_local(async move {
+            let mut was_live = false;
+            loop {
+                gloo_timers::future::TimeoutFuture::new(LIVE_RECOGNITION_INTERVAL_MS).await;
+                let cv_overlay_ref = cv_overlay_ref.get_untracked().unwrap();
+                // Never turn the camera on just for this
+                if !live_recognition.get_untracked() || !enabled.get_untracked() {
+                    if was_live {
+                        was_live = false;
+                        if let Some(cv_processor) = &*cv_available_rx.borrow() {
+                            draw_assignment_overlay(&cv_overlay_ref, cv_processor);
+                        }
+                    }
+                    continue;
+                }
+                was_live = true;
+
+                let image_data = capture_image_data(
+                    &video_ref.get_untracked().unwrap(),
+                    &canvas_ref.get_untracked().unwrap(),
+                    enabled,
+                    set_enabled,
+                    &playing_barrier,
+                )
+                .await;
+                let Some(cv_processor) = &*cv_available_rx.borrow() else {
+                    continue;
+                };
+                // The processor may be for another camera or resolution
+                let Some(pixels) = cv_processor.fit_image(
+                    &image_pixels(&image_data),
+                    image_data.width() as usize,
+                    image_data.height() as usize,
+                ) else {
+                    continue;
+                };
+                // The overlay may be sized for new pixel assignments instead
+                if cv_overlay_ref.width() as usize * cv_overlay_ref.height() as usize
+                    != pixels.len()
+                {
+                    continue;
+                }
+                let (_, confidence, stickers) = cv_processor.recognize_stickers(&pixels);
+                draw_recognition_overlay(
+                    &cv_overlay_ref,
+                    &cv_processor.pixel_assignment(),
+                    confidence,
+                    &stickers,
+                );
+            }
+        });
+    }
 
     #[cfg(feature = "hydrate")]
     {
@@ -342,55 +963,20 @@ pub fn Video(
                     continue;
                 };
                 info!("3");
-                let pixel_assignment = cv_processor.pixel_assignment_locations();
-                let mut overlay_data = vec![0u8; 4 * pixel_assignment.len()];
-                let mut assigned_pixels_count = 0;
-                for overlay_pixel_mut in overlay_data
-                    .chunks_exact_mut(4)
-                    .zip(pixel_assignment.iter())
-                    .filter_map(|(overlay_pixel_mut, &assigned_pixel)| {
-                        if assigned_pixel {
-                            Some(overlay_pixel_mut)
-                        } else {
-                            None
-                        }
-                    })
+                // Take pictures at the resolution that the processor was made for
+                let cv_overlay_ref = cv_overlay_ref.get_untracked().unwrap();
+                if let Some((width, height)) = cv_processor.resolution()
+                    && let (Ok(width), Ok(height)) = (u32::try_from(width), u32::try_from(height))
                 {
-                    assigned_pixels_count += 1;
-                    overlay_pixel_mut[0] = 255;
-                    overlay_pixel_mut[1] = 0;
-                    overlay_pixel_mut[2] = 255;
-                    overlay_pixel_mut[3] = 255;
+                    resize_canvases(
+                        &video_ref.get_untracked().unwrap(),
+                        &canvas_ref.get_untracked().unwrap(),
+                        &cv_overlay_ref,
+                        width,
+                        Some(height),
+                    );
                 }
-                info!(
-                    "Assigned {}/{} pixels",
-                    assigned_pixels_count,
-                    pixel_assignment.len()
-                );
-                let cv_overlay_ref = cv_overlay_ref.get_untracked().unwrap();
-                let overlay_height = cv_overlay_ref.height();
-                let overlay_width = cv_overlay_ref.width();
-                assert_eq!(
-                    overlay_height as usize * overlay_width as usize,
-                    pixel_assignment.len()
-                );
-
-                let opts = js_sys::Object::new();
-                js_sys::Reflect::set(&opts, &"willReadFrequently".into(), &true.into()).unwrap();
-                js_sys::Reflect::set(&opts, &"alpha".into(), &true.into()).unwrap();
-                let overlay_image_data = web_sys::ImageData::new_with_u8_clamped_array_and_sh(
-                    Clamped(&overlay_data),
-                    overlay_width,
-                    overlay_height,
-                )
-                .unwrap();
-                let ctx = cv_overlay_ref
-                    .get_context_with_context_options("2d", &opts)
-                    .unwrap()
-                    .unwrap()
-                    .dyn_into::<web_sys::CanvasRenderingContext2d>()
-                    .unwrap();
-                ctx.put_image_data(&overlay_image_data, 0.0, 0.0).unwrap();
+                draw_assignment_overlay(&cv_overlay_ref, cv_processor);
             }
         });
     }
@@ -413,35 +999,99 @@ pub fn Video(
         </div>
         <canvas node_ref=canvas_ref class="flex-1 min-w-0 border-2 border-amber-300" />
       </div>
+      <div class="flex flex-wrap gap-4 justify-center items-center">
+        <select
+          class="px-2 text-black cursor-pointer"
+          prop:value=move || camera_settings.get().device_id.unwrap_or_default()
+          on:change:target=move |ev| {
+            let device_id = ev.target().value();
+            camera_settings.update(|settings| settings.device_id = (!device_id.is_empty()).then_some(device_id));
+          }
+        >
+          <option value="">"Default camera"</option>
+          {move || {
+            camera_devices
+              .get()
+              .unwrap_or_default()
+              .into_iter()
+              .map(|(device_id, label)| {
+                let label = if label.is_empty() { format!("Unidentified: {device_id}") } else { label };
+                view! { <option value=device_id>{label}</option> }
+              })
+              .collect_view()
+          }}
+        </select>
+        <select
+          class="px-2 text-black cursor-pointer"
+          prop:value=move || {
+            camera_settings
+              .get()
+              .resolution
+              .map(|(width, height)| format!("{width}x{height}"))
+              .unwrap_or_default()
+          }
+          on:change:target=move |ev| {
+            let resolution = ev
+              .target()
+              .value()
+              .split_once('x')
+              .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+            camera_settings.update(|settings| settings.resolution = resolution);
+          }
+        >
+          <option value="">"Default resolution"</option>
+          {CAMERA_RESOLUTIONS
+            .iter()
+            .map(|(width, height)| {
+              view! { <option value=format!("{width}x{height}")>{format!("{width}x{height}")}</option> }
+            })
+            .collect_view()}
+        </select>
+        <label class="flex gap-2 items-center">
+          "Zoom"
+          <input
+            type="number"
+            min="1"
+            step="0.1"
+            placeholder="Default"
+            class="px-2 w-24 text-black"
+            prop:value=move || camera_settings.get().zoom.map(|zoom| zoom.to_string()).unwrap_or_default()
+            on:change:target=move |ev| {
+              let zoom = ev.target().value().parse().ok();
+              camera_settings.update(|settings| settings.zoom = zoom);
+            }
+          />
+        </label>
+        <label class="flex gap-2 items-center">
+          <input
+            type="checkbox"
+            prop:checked=move || camera_settings.get().lock_exposure
+            on:change:target=move |ev| {
+              camera_settings.update(|settings| settings.lock_exposure = ev.target().checked());
+            }
+          />
+          "Lock exposure"
+        </label>
+        <label class="flex gap-2 items-center">
+          <input
+            type="checkbox"
+            prop:checked=move || camera_settings.get().lock_white_balance
+            on:change:target=move |ev| {
+              camera_settings.update(|settings| settings.lock_white_balance = ev.target().checked());
+            }
+          />
+          "Lock white balance"
+        </label>
+        <label class="flex gap-2 items-center">
+          <input
+            type="checkbox"
+            prop:checked=move || camera_settings.get().lock_focus
+            on:change:target=move |ev| {
+              camera_settings.update(|settings| settings.lock_focus = ev.target().checked());
+            }
+          />
+          "Lock focus"
+        </label>
+      </div>
     }
-    // <select
-    //   on:change:target=select_camera_device
-    //   prop:value=move || camera_device.get().flatten().map(|d| d.device_id()).unwrap_or_default()
-    //   class="cursor-pointer"
-    // >
-    //   <Suspense fallback=move || {
-    //     view! { <option>"Loading..."</option> }
-    //   }>
-    //     {move || Suspend::new(async move {
-    //       view! {
-    //         {camera_devices
-    //           .await
-    //           .iter()
-    //           .map(|device| {
-    //             view! {
-    //               <option value=device
-    //                 .device_id()>
-    //                 {if device.label().is_empty() {
-    //                   format!("Unidentified: {}", device.device_id())
-    //                 } else {
-    //                   device.label()
-    //                 }}
-    //               </option>
-    //             }
-    //           })
-    //           .collect::<Vec<_>>()}
-    //       }
-    //     })}
-    //   </Suspense>
-    // </select>
 }